@@ -1,7 +1,7 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{UnorderedMap, Vector};
+use near_sdk::collections::{LookupMap, UnorderedMap, Vector};
 use near_sdk::json_types::U128;
-use near_sdk::{env, log, near_bindgen, AccountId, Promise, Gas, NearToken};
+use near_sdk::{env, log, near_bindgen, AccountId, Promise, PromiseOrValue, Gas, NearToken};
 use near_sdk::serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use schemars::JsonSchema;
@@ -16,6 +16,65 @@ pub struct DonorPayouts {
     admin: AccountId,
     potlock_nfts_contract: AccountId,
     token_contract: AccountId, 
+    treasury: AccountId,
+    archive_summaries: UnorderedMap<DonationType, ArchiveSummary>,
+    archived_count: u64,
+    archive_commitment: [u8; 32],
+    stats: Stats,
+    donation_type_stats: UnorderedMap<DonationType, DonationTypeStats>,
+    donations: Vector<DonationRecord>,
+    donations_by_donor: LookupMap<AccountId, Vector<u64>>,
+    donations_by_type: LookupMap<DonationType, Vector<u64>>,
+    campaigns: UnorderedMap<String, Campaign>,
+    projects: UnorderedMap<String, Project>,
+    accepted_tokens: Vec<AccountId>,
+    protocol_fee_bps: u32,
+    referral_fee_bps: u32,
+    fee_accounts: LookupMap<AccountId, FeeAccount>,
+    refund_window_ns: u64,
+    moderators: Vec<AccountId>,
+    min_donation: u128,
+    max_donation: Option<u128>,
+    campaign_donor_totals: LookupMap<(String, AccountId), u128>,
+    prepaid_balances: LookupMap<AccountId, u128>,
+    recurring_donations: UnorderedMap<u64, RecurringDonation>,
+    next_recurring_id: u64,
+    /// Position in `recurring_donations` where the next `process_recurring` call starts.
+    recurring_cursor: u64,
+    matching_pools: UnorderedMap<u64, MatchingPool>,
+    next_matching_pool_id: u64,
+    matching_history: LookupMap<u64, Vector<MatchRecord>>,
+    matching_donor_totals: LookupMap<(u64, AccountId), u128>,
+    reward_policies: UnorderedMap<DonationType, RewardPolicy>,
+    /// Position in `donations_by_type` up to which `compute_airdrops` has run.
+    reward_cursors: LookupMap<DonationType, u64>,
+    /// Latest distribution round of each donation type.
+    distributions: UnorderedMap<DonationType, Distribution>,
+    /// Earlier rounds, kept once a later one starts.
+    past_distributions: LookupMap<(DonationType, u32), Distribution>,
+    /// Donors snapshotted by each round, in the order they were first seen.
+    distribution_donors: LookupMap<(DonationType, u32), Vector<AccountId>>,
+    distribution_contributions: LookupMap<(DonationType, u32, AccountId), u128>,
+    /// Contributions counted by all rounds or rewarded by `compute_airdrops` so far, so snapshot
+    /// rounds only count what is new.
+    distributed_contributions: LookupMap<(DonationType, AccountId), u128>,
+    /// Donation id below which a snapshot round rewarded each donor's donations of a type, so
+    /// `compute_airdrops` passes them over.
+    snapshot_rewarded_through: LookupMap<(DonationType, AccountId), u64>,
+    human_registry: Option<HumanRegistry>,
+    human_checks: LookupMap<AccountId, HumanCheck>,
+    /// Donations per campaign and donor inside the campaign's donation window.
+    window_totals: LookupMap<(String, AccountId), WindowTotals>,
+    /// Rewards allocated per campaign and recipient, for reward caps and `max_rewards_per_donor`.
+    campaign_rewards: LookupMap<(String, AccountId), CampaignRewards>,
+    streak_config: StreakConfig,
+    snapshots: Vector<Snapshot>,
+    snapshot_donors: LookupMap<u64, Vector<AccountId>>,
+    snapshot_balances: LookupMap<(u64, AccountId), Vec<DonationTypeAmount>>,
+    /// Protocol and referral fees whose transfer failed, withdrawable by their recipient.
+    unclaimed_fees: LookupMap<AccountId, UnclaimedFees>,
+    gifts_by_payer: LookupMap<AccountId, Vector<u64>>,
+    open_matching_pools: LookupMap<(DonationType, Option<AccountId>), Vec<u64>>,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
@@ -38,6 +97,61 @@ pub struct AirdropRecord {
     pub paid: bool,
     pub reward_type: RewardType,
     pub donation_type: DonationType,
+    /// The donation this reward was computed from by `compute_airdrops`, if any.
+    pub donation_id: Option<u64>,
+}
+
+/// How `compute_airdrops` turns a NEAR donation into a token reward.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum RewardPolicy {
+    /// `ratio_bps` reward units per 10000 yoctoNEAR donated.
+    Ratio { ratio_bps: u32 },
+    /// The reward of the highest tier whose `min_amount` the donation reaches. Tiers are sorted
+    /// by `min_amount`, and donations below the first tier earn nothing.
+    Tiered { tiers: Vec<RewardTier> },
+    /// The same reward for every donation.
+    Flat {
+        #[schemars(with = "String")]
+        amount: U128,
+    },
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardTier {
+    #[schemars(with = "String")]
+    pub min_amount: U128,
+    #[schemars(with = "String")]
+    pub reward: U128,
+}
+
+impl RewardPolicy {
+    fn assert_valid(&self) {
+        match self {
+            RewardPolicy::Ratio { ratio_bps } => assert!(*ratio_bps > 0, "Reward ratio must be greater than 0"),
+            RewardPolicy::Tiered { tiers } => {
+                assert!(!tiers.is_empty(), "Tiered policy needs at least one tier");
+                assert!(
+                    tiers.windows(2).all(|pair| pair[0].min_amount.0 < pair[1].min_amount.0),
+                    "Tiers must be sorted by ascending min_amount"
+                );
+            }
+            RewardPolicy::Flat { amount } => assert!(amount.0 > 0, "Flat reward must be greater than 0"),
+        }
+    }
+
+    fn reward_for(&self, amount: u128) -> u128 {
+        match self {
+            RewardPolicy::Ratio { ratio_bps } => amount * *ratio_bps as u128 / BPS_DENOMINATOR,
+            RewardPolicy::Tiered { tiers } => tiers
+                .iter()
+                .rev()
+                .find(|tier| amount >= tier.min_amount.0)
+                .map_or(0, |tier| tier.reward.0),
+            RewardPolicy::Flat { amount } => amount.0,
+        }
+    }
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
@@ -59,822 +173,6942 @@ pub struct Donor {
     pub paid: bool,
     pub reward_types: Vec<RewardType>,
     pub donation_types: Vec<DonationType>,
+    /// Donation types the donor gave to under their own account or was airdropped under. Public
+    /// listings of donors who also gave anonymously only show these.
+    pub public_donation_types: Vec<DonationType>,
+    pub ft_donations: Vec<TokenAmount>,
+    /// NEAR and tokens given anonymously, which public listings leave out.
+    #[schemars(with = "String")]
+    pub anonymous_amount: U128,
+    pub anonymous_ft_donations: Vec<TokenAmount>,
+    /// Donations given under the donor's own account. Donors with none but anonymous ones are
+    /// listed as `anonymous`.
+    pub public_donation_count: u32,
+    /// NEAR added to this donor's donations by matching pools.
+    #[schemars(with = "String")]
+    pub matched_amount: U128,
+    pub ft_matched: Vec<TokenAmount>,
+    /// Set in `Flag` mode when the donor is rewarded or claims without a current human-registry
+    /// verification, and cleared once they verify.
+    pub unverified: bool,
+    /// Consecutive streak periods, ending with the one of `last_donation_at`, with a donation.
+    pub streak: u32,
+    pub longest_streak: u32,
+    pub last_donation_at: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, JsonSchema)]
-#[serde(crate = "near_sdk::serde")]
-pub struct PaginatedAirdropRecords {
-    pub records: Vec<AirdropRecord>,
-    pub has_more: bool,
-}
-
-#[derive(Serialize, Deserialize, JsonSchema)]
-#[serde(crate = "near_sdk::serde")]
-pub struct PaginatedDonors {
-    pub donors: Vec<Donor>,
-    pub has_more: bool,
-}
-
-impl Default for DonorPayouts {
-    fn default() -> Self {
+impl Donor {
+    fn new(wallet_id: AccountId) -> Self {
         Self {
-            donors: UnorderedMap::new(b"d"),
-            airdrop_records: Vector::new(b"a"),
-            total_distributed: 0,
-            admin: env::predecessor_account_id(),
-            potlock_nfts_contract: "potlock-nfts.testnet".parse().unwrap(),
-            token_contract: "token.testnet".parse().unwrap(),
+            wallet_id,
+            donation_amount: U128(0),
+            airdrop_amount: U128(0),
+            paid: false,
+            reward_types: vec![],
+            donation_types: vec![],
+            public_donation_types: vec![],
+            ft_donations: vec![],
+            anonymous_amount: U128(0),
+            anonymous_ft_donations: vec![],
+            public_donation_count: 0,
+            matched_amount: U128(0),
+            ft_matched: vec![],
+            unverified: false,
+            streak: 0,
+            longest_streak: 0,
+            last_donation_at: None,
         }
     }
-}
 
-#[near_bindgen]
-impl DonorPayouts {
-    #[init]
-    pub fn new(potlock_nfts_contract: Option<AccountId>, token_contract: Option<AccountId>) -> Self {
-        let admin = env::predecessor_account_id();
-        Self {
-            donors: UnorderedMap::new(b"d"),
-            airdrop_records: Vector::new(b"a"),
-            total_distributed: 0,
-            admin,
-            potlock_nfts_contract: potlock_nfts_contract.unwrap_or("potlock-nfts.testnet".parse().unwrap()),
-            token_contract: token_contract.unwrap_or("token.testnet".parse().unwrap()),
+    /// Counts a donation as anonymous or public, or takes it back off when `reverse` is set.
+    fn track_visibility(&mut self, donation: &DonationRecord, reverse: bool) {
+        let amount = donation.amount.0;
+        match (donation.anonymous, &donation.token_id, reverse) {
+            (false, _, false) => {
+                self.public_donation_count += 1;
+                self.add_public_donation_type(&donation.donation_type);
+            }
+            (false, _, true) => self.public_donation_count = self.public_donation_count.saturating_sub(1),
+            (true, None, false) => self.anonymous_amount = U128(self.anonymous_amount.0 + amount),
+            (true, None, true) => self.anonymous_amount = U128(self.anonymous_amount.0.saturating_sub(amount)),
+            (true, Some(token_id), false) => add_token_amount(&mut self.anonymous_ft_donations, token_id, amount),
+            (true, Some(token_id), true) => sub_token_amount(&mut self.anonymous_ft_donations, token_id, amount),
         }
     }
 
-    fn assert_admin(&self) {
-        assert_eq!(env::predecessor_account_id(), self.admin, "Only admin can call this function");
+    fn add_public_donation_type(&mut self, donation_type: &DonationType) {
+        if !self.public_donation_types.contains(donation_type) {
+            self.public_donation_types.push(donation_type.clone());
+        }
     }
 
-    #[payable]
-    pub fn log_airdrop(&mut self, recipient: AccountId, channel_id: String, donation_type: DonationType, amount: U128) {
-        self.assert_admin();
-        let amount_u128: u128 = amount.into();
-        let attached_amount = env::attached_deposit().as_yoctonear();
-        match &donation_type {
-            DonationType::Campaign { campaign_id } => assert!(campaign_id.len() <= 64, "Campaign ID must be 64 characters or less"),
-            DonationType::Project { project_id } => assert!(!project_id.is_empty(), "Project ID must not be empty"),
-            DonationType::Pot { pot_id } => assert!(env::is_valid_account_id(pot_id.as_bytes()), "Invalid pot_id"),
-            DonationType::Direct => (),
+    /// Whether public listings show the donor under `donation_type`. Donors listed under their
+    /// own wallet only appear under their public donation types.
+    fn listed_under(&self, donation_type: &DonationType) -> bool {
+        if self.public_donation_count == 0 {
+            self.donation_types.contains(donation_type)
+        } else {
+            self.public_donation_types.contains(donation_type)
         }
+    }
 
-        let reward_type = if channel_id.is_empty() {
-            RewardType::Token
+    fn gave_anonymously(&self) -> bool {
+        self.anonymous_amount.0 > 0 || self.anonymous_ft_donations.iter().any(|t| t.amount.0 > 0)
+    }
+
+    /// Hides the wallet of donors who only gave anonymously. Donors who also gave under their
+    /// own account are listed with just those donations.
+    fn public_view(mut self) -> Self {
+        if !self.gave_anonymously() {
+            return self;
+        }
+        if self.public_donation_count == 0 {
+            self.wallet_id = ANONYMOUS_ACCOUNT_ID.parse().unwrap();
         } else {
-            RewardType::NFT {
-                channel_id,
-                token_id: String::new(),
+            self.donation_amount = U128(self.donation_amount.0.saturating_sub(self.anonymous_amount.0));
+            for anonymous in &self.anonymous_ft_donations {
+                sub_token_amount(&mut self.ft_donations, &anonymous.token_id, anonymous.amount.0);
             }
-        };
+            self.ft_donations.retain(|t| t.amount.0 > 0);
+            let public_donation_types = &self.public_donation_types;
+            self.donation_types.retain(|t| public_donation_types.contains(t));
+        }
+        self.anonymous_amount = U128(0);
+        self.anonymous_ft_donations = vec![];
+        self
+    }
+}
 
-        let record = AirdropRecord {
-            recipient: recipient.clone(),
-            amount,
-            timestamp: env::block_timestamp(),
-            paid: false,
-            reward_type: reward_type.clone(),
-            donation_type: donation_type.clone(),
-        };
-        self.airdrop_records.push(&record);
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenAmount {
+    #[schemars(with = "String")]
+    pub token_id: AccountId,
+    #[schemars(with = "String")]
+    pub amount: U128,
+}
 
-        let mut donor = self.donors.get(&recipient).unwrap_or(Donor {
-            wallet_id: recipient.clone(),
-            donation_amount: U128(0),
-            airdrop_amount: U128(0),
-            paid: false,
-            reward_types: vec![],
-            donation_types: vec![],
-        });
+fn add_token_amount(amounts: &mut Vec<TokenAmount>, token_id: &AccountId, amount: u128) {
+    match amounts.iter_mut().find(|a| &a.token_id == token_id) {
+        Some(entry) => entry.amount = U128(entry.amount.0 + amount),
+        None => amounts.push(TokenAmount { token_id: token_id.clone(), amount: U128(amount) }),
+    }
+}
 
-        donor.airdrop_amount = U128(donor.airdrop_amount.0 + amount_u128);
-        donor.donation_amount = U128(donor.donation_amount.0 + attached_amount);
+fn sub_token_amount(amounts: &mut [TokenAmount], token_id: &AccountId, amount: u128) {
+    if let Some(entry) = amounts.iter_mut().find(|a| &a.token_id == token_id) {
+        entry.amount = U128(entry.amount.0.saturating_sub(amount));
+    }
+}
 
-        // Add donation_type if not already present
-        if !donor.donation_types.contains(&donation_type) {
-            donor.donation_types.push(donation_type.clone());
-        }
+/// Message carried by `ft_transfer_call` when donating fungible tokens.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtDonationMessage {
+    pub donation_type: DonationType,
+    pub memo: Option<String>,
+    #[schemars(with = "Option<String>")]
+    pub referrer_id: Option<AccountId>,
+    pub anonymous: Option<bool>,
+    /// Credits the donation to this account instead of the sender.
+    #[schemars(with = "Option<String>")]
+    pub beneficiary: Option<AccountId>,
+}
 
-        // Add reward_type if not already present
-        if !donor.reward_types.contains(&reward_type) {
-            donor.reward_types.push(reward_type.clone());
-        }
+/// Message carried by `ft_transfer_call` when funding a matching pool.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtMatchingPoolMessage {
+    pub matching_pool: MatchingPoolArgs,
+}
 
-        self.donors.insert(&recipient, &donor);
-        self.total_distributed += amount_u128;
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MatchingPoolArgs {
+    pub donation_type: DonationType,
+    pub ratio_bps: u32,
+    #[schemars(with = "Option<String>")]
+    pub per_donor_cap: Option<U128>,
+}
 
-        log!("Logged airdrop for {}: {} tokens, donation_type {:?}", recipient, amount_u128, donation_type);
-    }
+/// Optional details a donor can attach to a donation.
+#[derive(Default)]
+struct DonationOptions {
+    memo: Option<String>,
+    referrer_id: Option<AccountId>,
+    anonymous: bool,
+}
 
-    #[payable]
-    pub fn record_donation(&mut self, donation_type: DonationType) {
-        let signer = env::predecessor_account_id();
-        let attached_amount = env::attached_deposit().as_yoctonear();
-        assert!(attached_amount > 0, "Attached deposit must be greater than 0");
-        match &donation_type {
-            DonationType::Campaign { campaign_id } => assert!(campaign_id.len() <= 64, "Campaign ID must be 64 characters or less"),
-            DonationType::Project { project_id } => assert!(!project_id.is_empty(), "Project ID must not be empty"),
-            DonationType::Pot { pot_id } => assert!(env::is_valid_account_id(pot_id.as_bytes()), "Invalid pot_id"),
-            DonationType::Direct => (),
-        }
+/// Fees paid by an account as a donor and earned as a referrer. Token fees are kept per token.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeAccount {
+    #[schemars(with = "String")]
+    pub protocol_fees_paid: U128,
+    #[schemars(with = "String")]
+    pub referral_fees_paid: U128,
+    pub ft_fees_paid: Vec<TokenAmount>,
+    #[schemars(with = "String")]
+    pub referral_fees_earned: U128,
+    pub ft_referral_fees_earned: Vec<TokenAmount>,
+    pub referral_count: u64,
+}
 
-        let mut donor = self.donors.get(&signer).unwrap_or(Donor {
-            wallet_id: signer.clone(),
-            donation_amount: U128(0),
-            airdrop_amount: U128(0),
-            paid: false,
-            reward_types: vec![],
-            donation_types: vec![],
-        });
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UnclaimedFees {
+    #[schemars(with = "String")]
+    pub near: U128,
+    pub tokens: Vec<TokenAmount>,
+}
 
-        donor.donation_amount = U128(donor.donation_amount.0 + attached_amount);
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeConfig {
+    pub protocol_fee_bps: u32,
+    pub referral_fee_bps: u32,
+    #[schemars(with = "String")]
+    pub treasury: AccountId,
+}
 
-        // Add donation_type if not already present
-        if !donor.donation_types.contains(&donation_type) {
-            donor.donation_types.push(donation_type.clone());
-        }
+/// Registry contract used to keep sybil accounts from earning rewards.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct HumanRegistry {
+    #[schemars(with = "String")]
+    pub contract_id: AccountId,
+    /// View method taking `{"account_id": ...}` and returning a bool, e.g. `is_human`.
+    pub method_name: String,
+    /// How long a registry answer is trusted before it has to be checked again.
+    pub cache_ttl_ns: u64,
+    pub mode: HumanCheckMode,
+}
 
-        self.donors.insert(&signer, &donor);
-        log!("Recorded donation of {} yoctoNEAR for {}, donation_type {:?}", attached_amount, signer, donation_type);
-    }
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum HumanCheckMode {
+    /// Unverified accounts cannot be logged for airdrops or claim rewards.
+    Reject,
+    /// Unverified accounts go through but are marked `unverified` on their donor entry.
+    Flag,
+}
 
-    #[payable]
-    pub fn send_nft_reward(&mut self) -> Promise {
-        let signer = env::predecessor_account_id();
-        let donor = self.donors.get(&signer).expect("Donor not found");
-        assert!(!donor.paid, "Payout already completed");
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct HumanCheck {
+    pub is_human: bool,
+    pub checked_at: u64,
+}
 
-        let channel_id = donor
-            .reward_types
-            .iter()
-            .find_map(|r| match r {
-                RewardType::NFT { channel_id, .. } => Some(channel_id.clone()),
-                _ => None,
-            })
-            .expect("No NFT reward type found for donor");
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Campaign {
+    pub id: String,
+    pub name: String,
+    #[schemars(with = "String")]
+    pub owner: AccountId,
+    pub start: u64,
+    pub end: u64,
+    #[schemars(with = "String")]
+    pub budget: U128,
+    #[schemars(with = "String")]
+    pub reward_token: AccountId,
+    pub created_at: u64,
+    pub closed: bool,
+    pub limits: DonationLimits,
+    /// NEAR accepted so far, net of refunds.
+    #[schemars(with = "String")]
+    pub raised: U128,
+    pub eligibility: EligibilityRules,
+    /// Most NFT rewards the campaign may hand out; `budget` bounds its token rewards.
+    pub nft_budget: Option<u32>,
+    pub reward_caps: RewardCaps,
+    #[schemars(with = "String")]
+    pub tokens_allocated: U128,
+    pub nfts_allocated: u32,
+}
 
-        log!("Initiating NFT mint for {}", signer);
+/// Most one donor may be allocated by a campaign. Unset fields are not enforced.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Default, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardCaps {
+    #[schemars(with = "Option<String>")]
+    pub per_donor_tokens: Option<U128>,
+    pub per_donor_nfts: Option<u32>,
+}
 
-        Promise::new(self.potlock_nfts_contract.clone())
-            .function_call(
-                "nft_mint".to_string(),
-                json!({
-                    "receiver_id": signer,
-                    "channel_id": channel_id,
-                    "proof": None::<String>,
-                })
-                .to_string()
-                .into_bytes(),
-                env::attached_deposit(),
-                Gas::from_tgas(120)
-            )
-            .then(
-                Self::ext(env::current_account_id())
-                    .with_static_gas(Gas::from_tgas(10))
-                    .on_nft_mint_callback(signer)
-            )
-    }
+/// Campaign donations one donor made inside the campaign's donation window, for its
+/// eligibility rules.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+struct WindowTotals {
+    near: u128,
+    tokens: Vec<TokenAmount>,
+    donation_count: u64,
+}
 
-    // #[payable]
-    // pub fn send_token_reward(&mut self) -> Promise {
-    //     let signer = env::predecessor_account_id();
-    //     let donor = self.donors.get(&signer).expect("Donor not found");
-    //     assert!(!donor.paid, "Payout already completed");
-    //     assert!(
-    //         donor.reward_types.contains(&RewardType::Token),
-    //         "Donor reward type does not include Token"
-    //     );
-    //     assert!(donor.airdrop_amount.0 > 0, "No tokens to payout");
+/// Rewards one donor has been allocated by one campaign.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CampaignRewards {
+    pub record_count: u32,
+    #[schemars(with = "String")]
+    pub tokens: U128,
+    pub nfts: u32,
+}
 
-    //     log!("Initiating token transfer of {} for {}", donor.airdrop_amount.0, signer);
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CampaignBudget {
+    #[schemars(with = "String")]
+    pub token_budget: U128,
+    #[schemars(with = "String")]
+    pub tokens_allocated: U128,
+    #[schemars(with = "String")]
+    pub tokens_remaining: U128,
+    pub nft_budget: Option<u32>,
+    pub nfts_allocated: u32,
+    /// `None` when the campaign has no NFT budget.
+    pub nfts_remaining: Option<u32>,
+    pub reward_caps: RewardCaps,
+}
 
-    //     Promise::new(self.token_contract.clone())
-    //         .function_call(
-    //             "ft_transfer".to_string(),
-    //             json!({
-    //                 "receiver_id": signer,
-    //                 "amount": donor.airdrop_amount,
-    //             })
-    //             .to_string()
-    //             .into_bytes(),
-    //             NearToken::from_yoctonear(1), // Standard 1 yoctoNEAR for FT transfer
-    //             Gas::from_tgas(50)
-    //         )
-    //         .then(
-    //             Self::ext(env::current_account_id())
-    //                 .with_static_gas(Gas::from_tgas(10))
-    //                 .on_token_transfer_callback(signer, donor.airdrop_amount)
-    //         )
-    // }
+/// Per-campaign bounds on NEAR donations. Unset fields are not enforced.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Default, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DonationLimits {
+    #[schemars(with = "Option<String>")]
+    pub min_amount: Option<U128>,
+    #[schemars(with = "Option<String>")]
+    pub max_amount: Option<U128>,
+    /// Cumulative amount one donor may give to the campaign.
+    #[schemars(with = "Option<String>")]
+    pub per_donor_cap: Option<U128>,
+    /// Total the campaign may raise.
+    #[schemars(with = "Option<String>")]
+    pub hard_cap: Option<U128>,
+}
 
+impl DonationLimits {
+    fn is_capped(&self) -> bool {
+        self.per_donor_cap.is_some() || self.hard_cap.is_some()
+    }
+}
 
+/// Who may be rewarded by a campaign. Unset fields are not enforced.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Default, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EligibilityRules {
+    /// NEAR the donor must have given to the campaign within the donation window.
+    #[schemars(with = "Option<String>")]
+    pub min_total_donation: Option<U128>,
+    /// Token totals that satisfy `min_total_donation` instead, one entry per token.
+    #[serde(default)]
+    pub min_token_donations: Vec<TokenAmount>,
+    /// Only campaign donations made at or after this time count.
+    pub donated_after: Option<u64>,
+    /// Only campaign donations made before this time count.
+    pub donated_before: Option<u64>,
+    /// Donation types the donor must also have given to.
+    pub required_donation_types: Vec<DonationType>,
+    pub max_rewards_per_donor: Option<u32>,
+    #[schemars(with = "Vec<String>")]
+    pub excluded_accounts: Vec<AccountId>,
+}
 
-    #[payable]
-    pub fn send_token_reward(&mut self) -> Promise {
-        let signer = env::predecessor_account_id();
-        let donor = self.donors.get(&signer).expect("Donor not found");
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EligibilityReport {
+    pub eligible: bool,
+    pub passed: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum CampaignStatus {
+    Upcoming,
+    Active,
+    Ended,
+    Closed,
+}
+
+impl Campaign {
+    pub fn status(&self, now: u64) -> CampaignStatus {
+        if self.closed {
+            CampaignStatus::Closed
+        } else if now < self.start {
+            CampaignStatus::Upcoming
+        } else if now < self.end {
+            CampaignStatus::Active
+        } else {
+            CampaignStatus::Ended
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Project {
+    pub id: String,
+    #[schemars(with = "String")]
+    pub beneficiary: AccountId,
+    pub name: String,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    pub active: bool,
+    pub created_at: u64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CampaignView {
+    #[serde(flatten)]
+    pub campaign: Campaign,
+    pub status: CampaignStatus,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DonationRecord {
+    pub id: u64,
+    /// The account credited with the donation and eligible for its rewards.
+    #[schemars(with = "String")]
+    pub donor: AccountId,
+    /// The account that paid; differs from `donor` for gifts and receives any refund.
+    #[schemars(with = "String")]
+    pub payer: AccountId,
+    /// `None` for NEAR, otherwise the NEP-141 token contract.
+    #[schemars(with = "Option<String>")]
+    pub token_id: Option<AccountId>,
+    #[schemars(with = "String")]
+    pub amount: U128,
+    pub timestamp: u64,
+    pub donation_type: DonationType,
+    pub memo: Option<String>,
+    /// Set by a moderator; the memo is kept but left out of every view.
+    pub memo_hidden: bool,
+    pub anonymous: bool,
+    pub status: DonationStatus,
+    #[schemars(with = "String")]
+    pub protocol_fee: U128,
+    #[schemars(with = "Option<String>")]
+    pub referrer_id: Option<AccountId>,
+    #[schemars(with = "String")]
+    pub referral_fee: U128,
+    /// What reaches the beneficiary once both fees are taken out of `amount`.
+    #[schemars(with = "String")]
+    pub net_amount: U128,
+    /// End of the refund window while the donation sits in escrow.
+    pub refundable_until: Option<u64>,
+    /// Set once the donation has been used to compute an airdrop, which locks it against refunds.
+    pub rewarded: bool,
+    /// Total drawn from matching pools, in the donation's token, paid out once it is forwarded.
+    #[schemars(with = "String")]
+    pub matched_amount: U128,
+    pub matches: Vec<MatchContribution>,
+    /// The donor's streak including this donation, counted once it can no longer be refunded
+    /// and 0 until then; `compute_airdrops` applies its multiplier.
+    pub streak: u32,
+    /// When the refund was started, so snapshots taken before it still count the donation.
+    pub refunded_at: Option<u64>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MatchContribution {
+    pub pool_id: u64,
+    #[schemars(with = "String")]
+    pub amount: U128,
+}
+
+impl DonationRecord {
+    /// Whether the donation counts towards distributions and snapshots. Pending donations
+    /// settle within a block; held and refunded ones never reached the beneficiary.
+    fn counts_for_distribution(&self) -> bool {
+        self.token_id.is_none()
+            && matches!(self.status, DonationStatus::Forwarded | DonationStatus::Escrowed | DonationStatus::Retained)
+    }
+
+    fn public_view(mut self) -> Self {
+        if self.memo_hidden {
+            self.memo = None;
+        }
+        if self.anonymous {
+            self.donor = ANONYMOUS_ACCOUNT_ID.parse().unwrap();
+            self.payer = self.donor.clone();
+        }
+        self
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum DonationStatus {
+    /// Attached to `log_airdrop` by the admin and kept by this contract.
+    Retained,
+    /// Kept in this contract during the refund window.
+    Escrowed,
+    /// Transfer to the beneficiary is in flight.
+    Pending,
+    Forwarded,
+    /// The transfer to the beneficiary failed and the funds wait for the donor to reclaim them.
+    Held,
+    Refunded,
+}
+
+/// A standing order that donates `amount` from the donor's prepaid balance every `interval_ns`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RecurringDonation {
+    pub id: u64,
+    #[schemars(with = "String")]
+    pub donor: AccountId,
+    #[schemars(with = "String")]
+    pub amount: U128,
+    pub interval_ns: u64,
+    pub donation_type: DonationType,
+    pub next_due: u64,
+    pub executed_count: u64,
+    /// Periods that came due but could not be paid, e.g. for lack of balance.
+    pub skipped_count: u64,
+    pub last_donation_id: Option<u64>,
+}
+
+/// Donor totals frozen as of `taken_at`, filled in over several `build_snapshot` calls.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Snapshot {
+    pub id: u64,
+    pub label: String,
+    pub taken_at: u64,
+    pub block_height: u64,
+    /// Number of donations recorded when the snapshot was taken; later ones are left out.
+    pub donation_cutoff: u64,
+    /// Donations scanned so far.
+    pub cursor: u64,
+    pub donor_count: u64,
+    #[schemars(with = "String")]
+    pub total_donated: U128,
+    pub complete: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DonationTypeAmount {
+    pub donation_type: DonationType,
+    #[schemars(with = "String")]
+    pub amount: U128,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SnapshotBalance {
+    #[schemars(with = "String")]
+    pub account_id: AccountId,
+    /// Cumulative NEAR donated per donation type.
+    pub amounts: Vec<DonationTypeAmount>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaginatedSnapshotBalances {
+    pub balances: Vec<SnapshotBalance>,
+    pub has_more: bool,
+}
+
+/// Reward multipliers for donors who give in consecutive periods.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StreakConfig {
+    /// Length of one streak period, e.g. 30 days for monthly giving.
+    pub period_ns: u64,
+    /// Sorted by `min_streak`; the highest one reached applies. Without one the multiplier is 1x.
+    pub multipliers: Vec<StreakMultiplier>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StreakMultiplier {
+    pub min_streak: u32,
+    /// 10000 is 1x.
+    pub multiplier_bps: u32,
+}
+
+impl StreakConfig {
+    fn multiplier_bps(&self, streak: u32) -> u32 {
+        self.multipliers
+            .iter()
+            .rev()
+            .find(|m| streak >= m.min_streak)
+            .map_or(BPS_DENOMINATOR as u32, |m| m.multiplier_bps)
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DonorStreak {
+    /// Zero once a full period has passed without a donation.
+    pub current_streak: u32,
+    pub longest_streak: u32,
+    pub last_donation_at: Option<u64>,
+    pub multiplier_bps: u32,
+}
+
+/// Sponsor funds that match donations of one donation type, in NEAR or a single token.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MatchingPool {
+    pub id: u64,
+    #[schemars(with = "String")]
+    pub sponsor: AccountId,
+    pub donation_type: DonationType,
+    /// `None` for NEAR. Only donations in the same token are matched.
+    #[schemars(with = "Option<String>")]
+    pub token_id: Option<AccountId>,
+    /// Match per donated unit, e.g. 10000 matches one to one.
+    pub ratio_bps: u32,
+    /// Most this pool will match for a single donor.
+    #[schemars(with = "Option<String>")]
+    pub per_donor_cap: Option<U128>,
+    #[schemars(with = "String")]
+    pub deposited: U128,
+    /// Funds not yet drawn by a donation.
+    #[schemars(with = "String")]
+    pub balance: U128,
+    /// Funds paid out to beneficiaries.
+    #[schemars(with = "String")]
+    pub matched: U128,
+    pub created_at: u64,
+    pub closed: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MatchRecord {
+    pub donation_id: u64,
+    #[schemars(with = "String")]
+    pub amount: U128,
+    pub timestamp: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum DistributionMode {
+    /// Each donor is weighted by the square root of their total contribution.
+    Quadratic,
+    /// Each donor is weighted by their total contribution.
+    ProRata,
+}
+
+impl DistributionMode {
+    fn weight(&self, contributed: u128) -> u128 {
+        match self {
+            DistributionMode::Quadratic => isqrt(contributed),
+            DistributionMode::ProRata => contributed,
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum DistributionStatus {
+    /// Summing up each donor's NEAR donations made before the distribution started.
+    Collecting,
+    /// Adding up the donor weights.
+    Weighing,
+    /// Writing one token airdrop record per donor.
+    Allocating,
+    Complete,
+}
+
+/// A reward budget split across the donors of one donation type over several
+/// `process_distribution` calls.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Distribution {
+    pub donation_type: DonationType,
+    /// 0 for the first distribution of a donation type, then one more for each later round.
+    pub round: u32,
+    pub mode: DistributionMode,
+    #[schemars(with = "String")]
+    pub budget: U128,
+    pub status: DistributionStatus,
+    /// Snapshot the donor totals are read from, instead of the live donation history.
+    pub snapshot_id: Option<u64>,
+    /// Position among the donations of this type where collecting starts, just past the
+    /// donations counted by the previous round.
+    pub donation_start: u64,
+    /// Number of donations of this type when the distribution started, or of donors in the
+    /// snapshot; later ones are left out.
+    pub donation_cutoff: u64,
+    /// Donations with a lower id are counted by this round or an earlier one and can no longer
+    /// be refunded.
+    pub donation_id_cutoff: u64,
+    /// Position within the current phase.
+    pub cursor: u64,
+    pub donor_count: u64,
+    #[schemars(with = "String")]
+    pub total_contributed: U128,
+    #[schemars(with = "String")]
+    pub total_weight: U128,
+    #[schemars(with = "String")]
+    pub distributed: U128,
+    /// Budget left over from rounding each share down, known once the distribution completes.
+    #[schemars(with = "String")]
+    pub dust: U128,
+    pub started_at: u64,
+    pub completed_at: Option<u64>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ArchiveSummary {
+    pub donation_type: DonationType,
+    pub record_count: u64,
+    #[schemars(with = "String")]
+    pub token_amount: U128,
+    pub nft_count: u64,
+    pub last_archived_at: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DonationTypeStats {
+    #[schemars(with = "String")]
+    pub donated: U128,
+    pub ft_donated: Vec<TokenAmount>,
+    pub donation_count: u64,
+    #[schemars(with = "String")]
+    pub token_airdropped: U128,
+    pub nft_airdropped: u64,
+}
+
+impl DonationTypeStats {
+    fn add_donation(&mut self, token_id: &Option<AccountId>, amount: u128) {
+        match token_id {
+            None => self.donated = U128(self.donated.0 + amount),
+            Some(token_id) => add_token_amount(&mut self.ft_donated, token_id, amount),
+        }
+        self.donation_count += 1;
+    }
+
+    fn remove_donation(&mut self, token_id: &Option<AccountId>, amount: u128) {
+        match token_id {
+            None => self.donated = U128(self.donated.0.saturating_sub(amount)),
+            Some(token_id) => sub_token_amount(&mut self.ft_donated, token_id, amount),
+        }
+        self.donation_count = self.donation_count.saturating_sub(1);
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DonationTypeBreakdown {
+    pub pot: DonationTypeStats,
+    pub campaign: DonationTypeStats,
+    pub direct: DonationTypeStats,
+    pub project: DonationTypeStats,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenRewardStats {
+    pub record_count: u64,
+    #[schemars(with = "String")]
+    pub paid_amount: U128,
+    #[schemars(with = "String")]
+    pub unpaid_amount: U128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftRewardStats {
+    pub record_count: u64,
+    pub paid_count: u64,
+    pub unpaid_count: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Stats {
+    #[schemars(with = "String")]
+    pub total_donated: U128,
+    pub ft_donated: Vec<TokenAmount>,
+    pub donation_count: u64,
+    #[schemars(with = "String")]
+    pub protocol_fees: U128,
+    #[schemars(with = "String")]
+    pub referral_fees: U128,
+    pub by_donation_type: DonationTypeBreakdown,
+    pub token_rewards: TokenRewardStats,
+    pub nft_rewards: NftRewardStats,
+}
+
+impl DonationTypeBreakdown {
+    fn get_mut(&mut self, donation_type: &DonationType) -> &mut DonationTypeStats {
+        match donation_type {
+            DonationType::Pot { .. } => &mut self.pot,
+            DonationType::Campaign { .. } => &mut self.campaign,
+            DonationType::Direct => &mut self.direct,
+            DonationType::Project { .. } => &mut self.project,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ArchiveStatus {
+    pub archived_count: u64,
+    pub commitment: String,
+    pub live_records: u64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ArchiveResult {
+    pub archived: u64,
+    pub next_cursor: u64,
+    pub done: bool,
+    pub freed_bytes: u64,
+    #[schemars(with = "String")]
+    pub refunded: U128,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaginatedArchiveSummaries {
+    pub summaries: Vec<ArchiveSummary>,
+    pub has_more: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaginatedAirdropRecords {
+    pub records: Vec<AirdropRecord>,
+    pub has_more: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaginatedCampaigns {
+    pub campaigns: Vec<CampaignView>,
+    pub has_more: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaginatedProjects {
+    pub projects: Vec<Project>,
+    pub has_more: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaginatedDonations {
+    pub donations: Vec<DonationRecord>,
+    pub has_more: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaginatedRecurringDonations {
+    pub recurring_donations: Vec<RecurringDonation>,
+    pub has_more: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaginatedMatchingPools {
+    pub pools: Vec<MatchingPool>,
+    pub has_more: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaginatedMatchRecords {
+    pub records: Vec<MatchRecord>,
+    pub has_more: bool,
+}
+
+/// One entry of a `log_airdrops` batch. `deposit` is recorded as a retained donation from the
+/// recipient, like the attached deposit of `log_airdrop`.
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AirdropInput {
+    #[schemars(with = "String")]
+    pub recipient: AccountId,
+    /// Empty for a token reward.
+    pub channel_id: String,
+    pub donation_type: DonationType,
+    #[schemars(with = "String")]
+    pub amount: U128,
+    #[serde(default)]
+    #[schemars(with = "String")]
+    pub deposit: U128,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AirdropRejection {
+    pub index: u64,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LogAirdropsResult {
+    /// Indexes of the entries that were logged.
+    pub applied: Vec<u64>,
+    pub rejected: Vec<AirdropRejection>,
+    /// Index of the first entry left unprocessed for lack of gas; equals the batch length when
+    /// every entry was processed.
+    pub next_index: u64,
+    /// Deposits of rejected and unprocessed entries, plus any excess, sent back to the caller.
+    #[schemars(with = "String")]
+    pub refunded: U128,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ComputeAirdropsResult {
+    pub airdrops_created: u64,
+    #[schemars(with = "String")]
+    pub total_amount: U128,
+    /// Donations passed over for good because their donor failed the eligibility rules, was
+    /// answered as not human by the registry or reached the per-donor token cap.
+    pub skipped: u64,
+    /// Why the batch stopped at a donation that may still be rewarded: its donor has no current
+    /// human check, or the campaign's token budget is spent. The next call retries it.
+    pub blocked_by: Option<String>,
+    /// Position in the donation type's history where the next call starts.
+    pub next_index: u64,
+    pub remaining: u64,
+}
+
+/// What `compute_airdrops` would pay for a NEAR donation made now.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardPreview {
+    /// Why the donation itself would be refused; no reward is previewed in that case.
+    pub rejection: Option<String>,
+    /// Why an accepted donation would earn no reward: the donor fails the campaign's
+    /// eligibility rules, or the registry rejects accounts without a cached human check.
+    pub reward_rejection: Option<String>,
+    /// The part of the donation that would be kept, after any campaign caps.
+    #[schemars(with = "String")]
+    pub accepted_amount: U128,
+    /// Reward under the donation type's policy, before multipliers and caps.
+    #[schemars(with = "String")]
+    pub base_reward: U128,
+    /// The donor's streak once this donation is counted.
+    pub streak: u32,
+    pub multiplier_bps: u32,
+    /// True when a campaign budget or per-donor cap lowered the reward.
+    pub capped: bool,
+    #[schemars(with = "String")]
+    pub reward: U128,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PaginatedDonors {
+    pub donors: Vec<Donor>,
+    pub has_more: bool,
+}
+
+/// Contract state as written by the first release, before the donation ledger and campaign
+/// registry. Only read by `migrate`.
+#[derive(BorshDeserialize, BorshSerialize)]
+struct DonorPayoutsV0 {
+    donors: UnorderedMap<AccountId, DonorV0>,
+    airdrop_records: Vector<AirdropRecordV0>,
+    total_distributed: u128,
+    admin: AccountId,
+    potlock_nfts_contract: AccountId,
+    token_contract: AccountId,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+struct DonorV0 {
+    wallet_id: AccountId,
+    donation_amount: U128,
+    airdrop_amount: U128,
+    paid: bool,
+    reward_types: Vec<RewardType>,
+    donation_types: Vec<DonationType>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+struct AirdropRecordV0 {
+    recipient: AccountId,
+    amount: U128,
+    timestamp: u64,
+    paid: bool,
+    reward_type: RewardType,
+    donation_type: DonationType,
+}
+
+impl From<DonorV0> for Donor {
+    fn from(old: DonorV0) -> Self {
+        Self {
+            donation_amount: old.donation_amount,
+            airdrop_amount: old.airdrop_amount,
+            paid: old.paid,
+            reward_types: old.reward_types,
+            public_donation_types: old.donation_types.clone(),
+            donation_types: old.donation_types,
+            ..Donor::new(old.wallet_id)
+        }
+    }
+}
+
+impl From<AirdropRecordV0> for AirdropRecord {
+    fn from(old: AirdropRecordV0) -> Self {
+        Self {
+            recipient: old.recipient,
+            amount: old.amount,
+            timestamp: old.timestamp,
+            paid: old.paid,
+            reward_type: old.reward_type,
+            donation_type: old.donation_type,
+            donation_id: None,
+        }
+    }
+}
+
+impl Default for DonorPayouts {
+    fn default() -> Self {
+        Self {
+            donors: UnorderedMap::new(b"d"),
+            airdrop_records: Vector::new(b"a"),
+            total_distributed: 0,
+            admin: env::predecessor_account_id(),
+            potlock_nfts_contract: "potlock-nfts.testnet".parse().unwrap(),
+            token_contract: "token.testnet".parse().unwrap(),
+            treasury: env::predecessor_account_id(),
+            archive_summaries: UnorderedMap::new(b"s"),
+            archived_count: 0,
+            archive_commitment: [0; 32],
+            stats: Stats::default(),
+            donation_type_stats: UnorderedMap::new(b"t"),
+            donations: Vector::new(b"r"),
+            donations_by_donor: LookupMap::new(b"o"),
+            donations_by_type: LookupMap::new(b"y"),
+            campaigns: UnorderedMap::new(b"c"),
+            projects: UnorderedMap::new(b"p"),
+            accepted_tokens: vec![],
+            protocol_fee_bps: 0,
+            referral_fee_bps: 0,
+            fee_accounts: LookupMap::new(b"f"),
+            refund_window_ns: 0,
+            moderators: vec![],
+            min_donation: 0,
+            max_donation: None,
+            campaign_donor_totals: LookupMap::new(b"l"),
+            window_totals: LookupMap::new(b"cw".to_vec()),
+            prepaid_balances: LookupMap::new(b"b"),
+            recurring_donations: UnorderedMap::new(b"u"),
+            next_recurring_id: 0,
+            recurring_cursor: 0,
+            matching_pools: UnorderedMap::new(b"m"),
+            next_matching_pool_id: 0,
+            matching_history: LookupMap::new(b"h"),
+            matching_donor_totals: LookupMap::new(b"e"),
+            reward_policies: UnorderedMap::new(b"w"),
+            reward_cursors: LookupMap::new(b"k"),
+            distributions: UnorderedMap::new(b"q"),
+            past_distributions: LookupMap::new(b"qp".to_vec()),
+            distribution_donors: LookupMap::new(b"qd".to_vec()),
+            distribution_contributions: LookupMap::new(b"qc".to_vec()),
+            distributed_contributions: LookupMap::new(b"qt".to_vec()),
+            snapshot_rewarded_through: LookupMap::new(b"qs".to_vec()),
+            human_registry: None,
+            human_checks: LookupMap::new(b"z"),
+            campaign_rewards: LookupMap::new(b"cr".to_vec()),
+            streak_config: StreakConfig { period_ns: DEFAULT_STREAK_PERIOD_NS, multipliers: vec![] },
+            snapshots: Vector::new(b"i"),
+            snapshot_donors: LookupMap::new(b"v"),
+            snapshot_balances: LookupMap::new(b"x"),
+            unclaimed_fees: LookupMap::new(b"uf".to_vec()),
+            gifts_by_payer: LookupMap::new(b"dp".to_vec()),
+            open_matching_pools: LookupMap::new(b"mo".to_vec()),
+        }
+    }
+}
+
+const MAX_MEMO_BYTES: usize = 280;
+/// Stands in for the account of anonymous donors in public listings.
+const ANONYMOUS_ACCOUNT_ID: &str = "anonymous";
+const MAX_PROTOCOL_FEE_BPS: u32 = 1_000;
+const MAX_REFERRAL_FEE_BPS: u32 = 1_000;
+const BPS_DENOMINATOR: u128 = 10_000;
+const DEFAULT_STREAK_PERIOD_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+/// Donations or donors handled by each `finalize_round` call.
+const FINALIZE_ROUND_BATCH: u64 = 50;
+/// Schedules visited by each `process_recurring` call.
+const MAX_RECURRING_BATCH: u64 = 10;
+/// Gas `process_recurring` keeps in hand before charging another schedule: the forward's
+/// transfers and its callback, which reserves 60 Tgas once a matching pool applies.
+const RECURRING_DONATION_GAS: Gas = Gas::from_tgas(75);
+/// Longest allowed recurring interval, so schedule arithmetic cannot overflow.
+const MAX_RECURRING_INTERVAL_NS: u64 = 366 * 24 * 60 * 60 * 1_000_000_000;
+const MAX_AIRDROP_BATCH: usize = 100;
+/// Open pools per donation type and token. Every forwarded donation visits each of them.
+const MAX_OPEN_MATCHING_POOLS: usize = 10;
+/// Gas `log_airdrops` keeps in hand before starting another entry, enough to log it and still
+/// refund unused deposits.
+const LOG_AIRDROP_ENTRY_GAS: Gas = Gas::from_tgas(10);
+
+const EVENT_STANDARD: &str = "potlock_donor_payout";
+const EVENT_VERSION: &str = "1.0.0";
+
+/// Emits a NEP-297 event log so indexers can follow state changes.
+fn emit_event(event: &str, data: Value) {
+    log!(
+        "EVENT_JSON:{}",
+        json!({
+            "standard": EVENT_STANDARD,
+            "version": EVENT_VERSION,
+            "event": event,
+            "data": [data],
+        })
+    );
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Integer square root, rounded down.
+fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    let mut x = 1u128 << (n.ilog2() / 2 + 1);
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+/// `a * b / c` rounded down, for `b <= c`, without overflowing on the intermediate product.
+fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    if let Some(product) = a.checked_mul(b) {
+        return product / c;
+    }
+    let mask = u64::MAX as u128;
+    let (a_lo, a_hi, b_lo, b_hi) = (a & mask, a >> 64, b & mask, b >> 64);
+    let (lo_lo, hi_lo, lo_hi, hi_hi) = (a_lo * b_lo, a_hi * b_lo, a_lo * b_hi, a_hi * b_hi);
+    let mid = (lo_lo >> 64) + (hi_lo & mask) + (lo_hi & mask);
+    let lo = (lo_lo & mask) | (mid << 64);
+    let hi = hi_hi + (hi_lo >> 64) + (lo_hi >> 64) + (mid >> 64);
+
+    // Binary long division of the 256-bit product `hi:lo` by `c`.
+    let (mut quotient, mut remainder) = (0u128, 0u128);
+    for bit in (0..256).rev() {
+        let next = if bit >= 128 { (hi >> (bit - 128)) & 1 } else { (lo >> bit) & 1 };
+        let overflow = remainder >> 127 == 1;
+        remainder = (remainder << 1) | next;
+        quotient <<= 1;
+        if overflow || remainder >= c {
+            remainder = remainder.wrapping_sub(c);
+            quotient |= 1;
+        }
+    }
+    quotient
+}
+
+/// Storage prefix for a collection nested under `key` in a parent map with prefix `tag`.
+fn nested_prefix<K: BorshSerialize>(tag: &[u8], key: &K) -> Vec<u8> {
+    let mut prefix = tag.to_vec();
+    prefix.extend(env::sha256_array(&borsh::to_vec(key).unwrap()));
+    prefix
+}
+
+// `#[near_bindgen]` only forwards `cfg` attributes to the generated `ext` wrapper, so the lint
+// is also allowed on a module around the one method that takes the requested positional arguments.
+#[allow(clippy::too_many_arguments)]
+mod campaign_registry {
+    use super::*;
+
+    #[near_bindgen]
+    impl DonorPayouts {
+        #[allow(clippy::too_many_arguments)]
+        pub fn create_campaign(
+            &mut self,
+            id: String,
+            name: String,
+            owner: AccountId,
+            start: u64,
+            end: u64,
+            budget: U128,
+            reward_token: Option<AccountId>,
+        ) -> CampaignView {
+            self.assert_admin();
+            assert!(!id.is_empty() && id.len() <= 64, "Campaign ID must be between 1 and 64 characters");
+            assert!(self.campaigns.get(&id).is_none(), "Campaign already exists");
+            assert!(start < end, "Campaign start must be before its end");
+
+            let campaign = Campaign {
+                id: id.clone(),
+                name,
+                owner,
+                start,
+                end,
+                budget,
+                reward_token: reward_token.unwrap_or(self.token_contract.clone()),
+                created_at: env::block_timestamp(),
+                closed: false,
+                limits: DonationLimits::default(),
+                raised: U128(0),
+                eligibility: EligibilityRules::default(),
+                nft_budget: None,
+                reward_caps: RewardCaps::default(),
+                tokens_allocated: U128(0),
+                nfts_allocated: 0,
+            };
+            self.campaigns.insert(&id, &campaign);
+            emit_event("campaign_created", json!({ "campaign_id": id, "owner": campaign.owner, "budget": campaign.budget }));
+            log!("Created campaign {} owned by {}", id, campaign.owner);
+            self.campaign_view(campaign)
+        }
+    }
+}
+
+#[near_bindgen]
+impl DonorPayouts {
+    #[init]
+    pub fn new(potlock_nfts_contract: Option<AccountId>, token_contract: Option<AccountId>) -> Self {
+        Self {
+            potlock_nfts_contract: potlock_nfts_contract.unwrap_or("potlock-nfts.testnet".parse().unwrap()),
+            token_contract: token_contract.unwrap_or("token.testnet".parse().unwrap()),
+            ..Self::default()
+        }
+    }
+
+    /// Upgrades state written by the first release. Donors and airdrop records are rewritten in
+    /// the current layout under their old prefixes, and the reward stats are rebuilt from the
+    /// records. Every other collection starts empty. Runs in one transaction, so it is sized for
+    /// the donor and record counts of the live contract.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let mut old: DonorPayoutsV0 = env::state_read().expect("No state to migrate");
+        let donors: Vec<(AccountId, DonorV0)> = old.donors.to_vec();
+        let records: Vec<AirdropRecordV0> = old.airdrop_records.to_vec();
+        // The entries are read out and cleared first, as rewriting them in place would make the
+        // collections deserialize the old values with the new layout.
+        old.donors.clear();
+        old.airdrop_records.clear();
+        let mut contract = Self {
+            total_distributed: old.total_distributed,
+            admin: old.admin.clone(),
+            potlock_nfts_contract: old.potlock_nfts_contract,
+            token_contract: old.token_contract,
+            treasury: old.admin,
+            ..Self::default()
+        };
+
+        for (account_id, donor) in donors {
+            contract.donors.insert(&account_id, &Donor::from(donor));
+        }
+        for record in records {
+            let record = AirdropRecord::from(record);
+            contract.airdrop_records.push(&record);
+            contract.record_airdrop_stats(&record.donation_type, &record.reward_type, record.amount.0);
+            if record.paid {
+                contract.record_payout_stats(&record);
+            }
+        }
+
+        emit_event(
+            "state_migrated",
+            json!({ "donors": contract.donors.len(), "airdrop_records": contract.airdrop_records.len() }),
+        );
+        contract
+    }
+
+    fn assert_admin(&self) {
+        assert_eq!(env::predecessor_account_id(), self.admin, "Only admin can call this function");
+    }
+
+    pub fn set_treasury(&mut self, treasury: AccountId) {
+        self.assert_admin();
+        self.treasury = treasury;
+        log!("Treasury set to {}", self.treasury);
+    }
+
+    /// Checks the donation type is well formed and refers to a registered campaign or project.
+    fn assert_valid_donation_type(&self, donation_type: &DonationType) {
+        self.check_donation_type(donation_type).unwrap_or_else(|err| env::panic_str(&err));
+    }
+
+    fn check_donation_type(&self, donation_type: &DonationType) -> Result<(), String> {
+        let error = match donation_type {
+            DonationType::Campaign { campaign_id } if campaign_id.len() > 64 => "Campaign ID must be 64 characters or less",
+            DonationType::Campaign { campaign_id } if self.campaigns.get(campaign_id).is_none() => "Campaign not found",
+            DonationType::Project { project_id } if project_id.is_empty() => "Project ID must not be empty",
+            DonationType::Project { project_id } if self.projects.get(project_id).is_none() => "Project not found",
+            DonationType::Pot { pot_id } if !env::is_valid_account_id(pot_id.as_bytes()) => "Invalid pot_id",
+            _ => return Ok(()),
+        };
+        Err(error.to_string())
+    }
+
+    pub fn close_campaign(&mut self, campaign_id: String) {
+        let mut campaign = self.campaigns.get(&campaign_id).expect("Campaign not found");
+        let caller = env::predecessor_account_id();
+        assert!(caller == self.admin || caller == campaign.owner, "Only admin or campaign owner can close a campaign");
+        assert!(!campaign.closed, "Campaign already closed");
+        campaign.closed = true;
+        self.campaigns.insert(&campaign_id, &campaign);
+        emit_event("campaign_closed", json!({ "campaign_id": campaign_id }));
+        log!("Closed campaign {}", campaign_id);
+    }
+
+    pub fn register_project(
+        &mut self,
+        project_id: String,
+        beneficiary: AccountId,
+        name: String,
+        description: Option<String>,
+        url: Option<String>,
+    ) -> Project {
+        self.assert_admin();
+        assert!(!project_id.is_empty() && project_id.len() <= 64, "Project ID must be between 1 and 64 characters");
+        assert!(self.projects.get(&project_id).is_none(), "Project already exists");
+
+        let project = Project {
+            id: project_id.clone(),
+            beneficiary,
+            name,
+            description,
+            url,
+            active: true,
+            created_at: env::block_timestamp(),
+        };
+        self.projects.insert(&project_id, &project);
+        emit_event("project_registered", json!({ "project_id": project_id, "beneficiary": project.beneficiary }));
+        log!("Registered project {} with beneficiary {}", project_id, project.beneficiary);
+        project
+    }
+
+    pub fn update_project(
+        &mut self,
+        project_id: String,
+        beneficiary: Option<AccountId>,
+        name: Option<String>,
+        description: Option<String>,
+        url: Option<String>,
+    ) -> Project {
+        self.assert_admin();
+        let mut project = self.projects.get(&project_id).expect("Project not found");
+        if let Some(beneficiary) = beneficiary {
+            project.beneficiary = beneficiary;
+        }
+        if let Some(name) = name {
+            project.name = name;
+        }
+        if description.is_some() {
+            project.description = description;
+        }
+        if url.is_some() {
+            project.url = url;
+        }
+        self.projects.insert(&project_id, &project);
+        emit_event("project_updated", json!({ "project_id": project_id, "beneficiary": project.beneficiary }));
+        log!("Updated project {}", project_id);
+        project
+    }
+
+    pub fn set_project_active(&mut self, project_id: String, active: bool) {
+        self.assert_admin();
+        let mut project = self.projects.get(&project_id).expect("Project not found");
+        project.active = active;
+        self.projects.insert(&project_id, &project);
+        emit_event("project_updated", json!({ "project_id": project_id, "active": active }));
+        log!("Project {} active: {}", project_id, active);
+    }
+
+    pub fn set_campaign_limits(&mut self, campaign_id: String, limits: DonationLimits) {
+        let mut campaign = self.campaigns.get(&campaign_id).expect("Campaign not found");
+        let caller = env::predecessor_account_id();
+        assert!(caller == self.admin || caller == campaign.owner, "Only admin or campaign owner can set campaign limits");
+        if let (Some(min), Some(max)) = (limits.min_amount, limits.max_amount) {
+            assert!(min.0 <= max.0, "Minimum donation cannot exceed the maximum");
+        }
+        campaign.limits = limits;
+        self.campaigns.insert(&campaign_id, &campaign);
+        emit_event("campaign_limits_updated", json!({ "campaign_id": campaign_id, "limits": campaign.limits }));
+        log!("Updated donation limits for campaign {}", campaign_id);
+    }
+
+    pub fn set_campaign_eligibility(&mut self, campaign_id: String, rules: EligibilityRules) {
+        let mut campaign = self.campaigns.get(&campaign_id).expect("Campaign not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == self.admin || caller == campaign.owner,
+            "Only admin or campaign owner can set eligibility rules"
+        );
+        if let (Some(after), Some(before)) = (rules.donated_after, rules.donated_before) {
+            assert!(after < before, "Donation window must end after it starts");
+        }
+        // Window totals are kept as donations arrive, so the window is fixed once they do.
+        let window_changed = (rules.donated_after, rules.donated_before)
+            != (campaign.eligibility.donated_after, campaign.eligibility.donated_before);
+        assert!(
+            !window_changed || self.donations_by_type.get(&DonationType::Campaign { campaign_id: campaign_id.clone() }).is_none(),
+            "The donation window cannot change once the campaign has donations"
+        );
+        for donation_type in &rules.required_donation_types {
+            self.assert_valid_donation_type(donation_type);
+        }
+        campaign.eligibility = rules;
+        self.campaigns.insert(&campaign_id, &campaign);
+        emit_event(
+            "campaign_eligibility_updated",
+            json!({ "campaign_id": campaign_id, "rules": campaign.eligibility }),
+        );
+    }
+
+    /// Checks `account_id` against each rule the campaign sets. `new_reward` counts the reward
+    /// about to be logged against `max_rewards_per_donor`; claims only check the rest.
+    fn evaluate_eligibility(&self, account_id: &AccountId, campaign: &Campaign, new_reward: bool) -> EligibilityReport {
+        let rules = &campaign.eligibility;
+        let mut report = EligibilityReport { eligible: true, passed: vec![], failed: vec![] };
+        let mut check = |rule: &str, passed: bool| {
+            if passed {
+                report.passed.push(rule.to_string());
+            } else {
+                report.failed.push(rule.to_string());
+            }
+        };
+
+        if !rules.excluded_accounts.is_empty() {
+            check("excluded_accounts", !rules.excluded_accounts.contains(account_id));
+        }
+        let windowed = rules.donated_after.is_some() || rules.donated_before.is_some();
+        let has_minimum = rules.min_total_donation.is_some() || !rules.min_token_donations.is_empty();
+        if has_minimum || windowed {
+            let totals = self.window_totals.get(&(campaign.id.clone(), account_id.clone())).unwrap_or_default();
+            if has_minimum {
+                let near_met = rules.min_total_donation.is_some_and(|min| totals.near >= min.0);
+                let token_met = rules.min_token_donations.iter().any(|min| {
+                    totals.tokens.iter().any(|t| t.token_id == min.token_id && t.amount.0 >= min.amount.0)
+                });
+                check("min_total_donation", near_met || token_met);
+            }
+            if windowed {
+                check("donation_window", totals.donation_count > 0);
+            }
+        }
+        if !rules.required_donation_types.is_empty() {
+            let donation_types = self.donors.get(account_id).map(|d| d.donation_types).unwrap_or_default();
+            check(
+                "required_donation_types",
+                rules.required_donation_types.iter().all(|t| donation_types.contains(t)),
+            );
+        }
+        if let Some(max) = rules.max_rewards_per_donor.filter(|_| new_reward) {
+            let rewards = self.campaign_rewards.get(&(campaign.id.clone(), account_id.clone())).unwrap_or_default();
+            check("max_rewards_per_donor", rewards.record_count < max);
+        }
+        report.eligible = report.failed.is_empty();
+        report
+    }
+
+    /// Panics unless `account_id` passes the eligibility rules of the campaign behind `donation_type`.
+    fn assert_eligible(&self, account_id: &AccountId, donation_type: &DonationType, new_reward: bool) {
+        self.check_eligible(account_id, donation_type, new_reward).unwrap_or_else(|err| env::panic_str(&err));
+    }
+
+    fn check_eligible(&self, account_id: &AccountId, donation_type: &DonationType, new_reward: bool) -> Result<(), String> {
+        let DonationType::Campaign { campaign_id } = donation_type else {
+            return Ok(());
+        };
+        let campaign = self.campaigns.get(campaign_id).ok_or("Campaign not found")?;
+        let report = self.evaluate_eligibility(account_id, &campaign, new_reward);
+        if !report.eligible {
+            return Err(format!(
+                "{} is not eligible for campaign {}: failed {}",
+                account_id,
+                campaign_id,
+                report.failed.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the claiming donor still passes the rules of every campaign that rewarded them.
+    fn assert_eligible_to_claim(&self, donor: &Donor) {
+        for donation_type in &donor.donation_types {
+            if let DonationType::Campaign { campaign_id } = donation_type {
+                let key = (campaign_id.clone(), donor.wallet_id.clone());
+                if self.campaign_rewards.get(&key).is_some_and(|r| r.record_count > 0) {
+                    self.assert_eligible(&donor.wallet_id, donation_type, false);
+                }
+            }
+        }
+    }
+
+    /// Sets the campaign's token and NFT reward budgets and per-donor caps. Budgets cannot be
+    /// set below what has already been allocated.
+    pub fn set_campaign_budget(
+        &mut self,
+        campaign_id: String,
+        budget: U128,
+        nft_budget: Option<u32>,
+        reward_caps: RewardCaps,
+    ) -> CampaignBudget {
+        let mut campaign = self.campaigns.get(&campaign_id).expect("Campaign not found");
+        let caller = env::predecessor_account_id();
+        assert!(caller == self.admin || caller == campaign.owner, "Only admin or campaign owner can set the campaign budget");
+        assert!(budget.0 >= campaign.tokens_allocated.0, "Token budget is below the tokens already allocated");
+        assert!(
+            nft_budget.is_none_or(|nfts| nfts >= campaign.nfts_allocated),
+            "NFT budget is below the NFTs already allocated"
+        );
+        campaign.budget = budget;
+        campaign.nft_budget = nft_budget;
+        campaign.reward_caps = reward_caps;
+        self.campaigns.insert(&campaign_id, &campaign);
+        emit_event(
+            "campaign_budget_updated",
+            json!({
+                "campaign_id": campaign_id,
+                "budget": campaign.budget,
+                "nft_budget": campaign.nft_budget,
+                "reward_caps": campaign.reward_caps,
+            }),
+        );
+        Self::campaign_budget(&campaign)
+    }
+
+    fn campaign_budget(campaign: &Campaign) -> CampaignBudget {
+        CampaignBudget {
+            token_budget: campaign.budget,
+            tokens_allocated: campaign.tokens_allocated,
+            tokens_remaining: U128(campaign.budget.0.saturating_sub(campaign.tokens_allocated.0)),
+            nft_budget: campaign.nft_budget,
+            nfts_allocated: campaign.nfts_allocated,
+            nfts_remaining: campaign.nft_budget.map(|nfts| nfts.saturating_sub(campaign.nfts_allocated)),
+            reward_caps: campaign.reward_caps.clone(),
+        }
+    }
+
+    /// Most reward tokens `recipient` can still be allocated under the campaign's budget and
+    /// per-donor cap. Unbounded for other donation types.
+    fn campaign_token_room(&self, recipient: &AccountId, donation_type: &DonationType) -> u128 {
+        let DonationType::Campaign { campaign_id } = donation_type else {
+            return u128::MAX;
+        };
+        let Some(campaign) = self.campaigns.get(campaign_id) else {
+            return u128::MAX;
+        };
+        let room = campaign.budget.0.saturating_sub(campaign.tokens_allocated.0);
+        match campaign.reward_caps.per_donor_tokens {
+            Some(cap) => {
+                let rewards = self.campaign_rewards.get(&(campaign_id.clone(), recipient.clone())).unwrap_or_default();
+                room.min(cap.0.saturating_sub(rewards.tokens.0))
+            }
+            None => room,
+        }
+    }
+
+    /// Fails if the reward would take the campaign over its budget or the recipient over a cap.
+    fn check_campaign_budget(
+        &self,
+        recipient: &AccountId,
+        donation_type: &DonationType,
+        reward_type: &RewardType,
+        amount: u128,
+    ) -> Result<(), String> {
+        let DonationType::Campaign { campaign_id } = donation_type else {
+            return Ok(());
+        };
+        let campaign = self.campaigns.get(campaign_id).ok_or("Campaign not found")?;
+        let rewards = self.campaign_rewards.get(&(campaign_id.clone(), recipient.clone())).unwrap_or_default();
+        match reward_type {
+            RewardType::Token => {
+                // Checked, as release builds wrap on overflow and would slip past the limits.
+                let allocated = campaign.tokens_allocated.0.checked_add(amount);
+                if allocated.is_none_or(|allocated| allocated > campaign.budget.0) {
+                    return Err(format!(
+                        "Airdrop exceeds the campaign token budget, {} remaining",
+                        campaign.budget.0.saturating_sub(campaign.tokens_allocated.0)
+                    ));
+                }
+                let donor_tokens = rewards.tokens.0.checked_add(amount);
+                if campaign.reward_caps.per_donor_tokens.is_some_and(|cap| donor_tokens.is_none_or(|tokens| tokens > cap.0)) {
+                    return Err("Airdrop exceeds the per-donor token cap".to_string());
+                }
+            }
+            RewardType::NFT { .. } => {
+                if campaign.nft_budget.is_some_and(|nft_budget| campaign.nfts_allocated >= nft_budget) {
+                    return Err("Airdrop exceeds the campaign NFT budget".to_string());
+                }
+                if campaign.reward_caps.per_donor_nfts.is_some_and(|cap| rewards.nfts >= cap) {
+                    return Err("Airdrop exceeds the per-donor NFT cap".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Contract-wide bounds on every NEAR donation, on top of any campaign limits.
+    pub fn set_donation_limits(&mut self, min_donation: U128, max_donation: Option<U128>) {
+        self.assert_admin();
+        if let Some(max) = max_donation {
+            assert!(min_donation.0 <= max.0, "Minimum donation cannot exceed the maximum");
+        }
+        self.min_donation = min_donation.0;
+        self.max_donation = max_donation.map(|max| max.0);
+        log!("Donation limits set to min {} max {:?}", self.min_donation, self.max_donation);
+    }
+
+    /// Checks that the campaign or project is open and the donation is within the global and
+    /// campaign limits, and returns how much of it can be accepted. Anything over the per-donor
+    /// or hard cap is left for the caller to refund.
+    fn check_donation(
+        &self,
+        donor_id: &AccountId,
+        token_id: &Option<AccountId>,
+        amount: u128,
+        donation_type: &DonationType,
+    ) -> Result<u128, String> {
+        let campaign = match donation_type {
+            DonationType::Campaign { campaign_id } => self.campaigns.get(campaign_id),
+            _ => None,
+        };
+        if let Some(campaign) = &campaign {
+            if campaign.status(env::block_timestamp()) != CampaignStatus::Active {
+                return Err("Campaign is not accepting donations".to_string());
+            }
+        }
+        if let DonationType::Project { project_id } = donation_type {
+            if !self.projects.get(project_id).is_some_and(|p| p.active) {
+                return Err("Project is not active".to_string());
+            }
+        }
+        if token_id.is_some() {
+            if campaign.is_some_and(|c| c.limits.is_capped()) {
+                return Err("Token donations are not accepted by capped campaigns".to_string());
+            }
+            return Ok(amount);
+        }
+
+        if amount < self.min_donation {
+            return Err(format!("Donation is below the minimum of {} yoctoNEAR", self.min_donation));
+        }
+        if let Some(max) = self.max_donation.filter(|max| amount > *max) {
+            return Err(format!("Donation exceeds the maximum of {} yoctoNEAR", max));
+        }
+        let Some(campaign) = campaign else {
+            return Ok(amount);
+        };
+
+        let limits = &campaign.limits;
+        if let Some(min) = limits.min_amount.filter(|min| amount < min.0) {
+            return Err(format!("Donation is below the campaign minimum of {} yoctoNEAR", min.0));
+        }
+        if let Some(max) = limits.max_amount.filter(|max| amount > max.0) {
+            return Err(format!("Donation exceeds the campaign maximum of {} yoctoNEAR", max.0));
+        }
+
+        let mut accepted = amount;
+        if let Some(hard_cap) = limits.hard_cap {
+            accepted = accepted.min(hard_cap.0.saturating_sub(campaign.raised.0));
+        }
+        if let Some(per_donor_cap) = limits.per_donor_cap {
+            let given = self.campaign_donor_totals.get(&(campaign.id.clone(), donor_id.clone())).unwrap_or(0);
+            accepted = accepted.min(per_donor_cap.0.saturating_sub(given));
+        }
+        if accepted == 0 {
+            return Err("Campaign cap reached".to_string());
+        }
+        Ok(accepted)
+    }
+
+    /// Keeps the campaign's raised total and the donor's running total in step with NEAR donations.
+    fn adjust_campaign_raised(&mut self, donation: &DonationRecord, reverse: bool) {
+        let DonationType::Campaign { campaign_id } = &donation.donation_type else {
+            return;
+        };
+        self.adjust_window_totals(donation, reverse);
+        if donation.token_id.is_some() {
+            return;
+        }
+        let amount = donation.amount.0;
+        let mut campaign = self.campaigns.get(campaign_id).expect("Campaign not found");
+        campaign.raised = U128(if reverse { campaign.raised.0.saturating_sub(amount) } else { campaign.raised.0 + amount });
+        self.campaigns.insert(campaign_id, &campaign);
+
+        let key = (campaign_id.clone(), donation.donor.clone());
+        let given = self.campaign_donor_totals.get(&key).unwrap_or(0);
+        self.campaign_donor_totals.insert(&key, &(if reverse { given.saturating_sub(amount) } else { given + amount }));
+    }
+
+    /// Counts a campaign donation, NEAR or token, towards the donor's totals when it falls
+    /// inside the campaign's donation window.
+    fn adjust_window_totals(&mut self, donation: &DonationRecord, reverse: bool) {
+        let DonationType::Campaign { campaign_id } = &donation.donation_type else {
+            return;
+        };
+        let rules = self.campaigns.get(campaign_id).expect("Campaign not found").eligibility;
+        let in_window = rules.donated_after.is_none_or(|after| donation.timestamp >= after)
+            && rules.donated_before.is_none_or(|before| donation.timestamp < before);
+        if !in_window {
+            return;
+        }
+        let key = (campaign_id.clone(), donation.donor.clone());
+        let mut totals = self.window_totals.get(&key).unwrap_or_default();
+        let amount = donation.amount.0;
+        match (&donation.token_id, reverse) {
+            (None, false) => totals.near += amount,
+            (None, true) => totals.near = totals.near.saturating_sub(amount),
+            (Some(token_id), false) => add_token_amount(&mut totals.tokens, token_id, amount),
+            (Some(token_id), true) => sub_token_amount(&mut totals.tokens, token_id, amount),
+        }
+        totals.donation_count =
+            if reverse { totals.donation_count.saturating_sub(1) } else { totals.donation_count + 1 };
+        self.window_totals.insert(&key, &totals);
+    }
+
+    fn campaign_view(&self, campaign: Campaign) -> CampaignView {
+        let status = campaign.status(env::block_timestamp());
+        CampaignView { campaign, status }
+    }
+
+    fn record_donation_stats(&mut self, donation_type: &DonationType, token_id: &Option<AccountId>, amount: u128) {
+        match token_id {
+            None => self.stats.total_donated = U128(self.stats.total_donated.0 + amount),
+            Some(token_id) => add_token_amount(&mut self.stats.ft_donated, token_id, amount),
+        }
+        self.stats.donation_count += 1;
+        self.stats.by_donation_type.get_mut(donation_type).add_donation(token_id, amount);
+
+        let mut type_stats = self.donation_type_stats.get(donation_type).unwrap_or_default();
+        type_stats.add_donation(token_id, amount);
+        self.donation_type_stats.insert(donation_type, &type_stats);
+    }
+
+    fn record_airdrop_stats(&mut self, donation_type: &DonationType, reward_type: &RewardType, amount: u128) {
+        let mut type_stats = self.donation_type_stats.get(donation_type).unwrap_or_default();
+        let kind = self.stats.by_donation_type.get_mut(donation_type);
+        match reward_type {
+            RewardType::Token => {
+                kind.token_airdropped = U128(kind.token_airdropped.0 + amount);
+                type_stats.token_airdropped = U128(type_stats.token_airdropped.0 + amount);
+                self.stats.token_rewards.record_count += 1;
+                self.stats.token_rewards.unpaid_amount = U128(self.stats.token_rewards.unpaid_amount.0 + amount);
+            }
+            RewardType::NFT { .. } => {
+                kind.nft_airdropped += 1;
+                type_stats.nft_airdropped += 1;
+                self.stats.nft_rewards.record_count += 1;
+                self.stats.nft_rewards.unpaid_count += 1;
+            }
+        }
+        self.donation_type_stats.insert(donation_type, &type_stats);
+    }
+
+    /// Moves a record that has just been marked paid from the unpaid to the paid totals.
+    fn record_payout_stats(&mut self, record: &AirdropRecord) {
+        match record.reward_type {
+            RewardType::Token => {
+                let rewards = &mut self.stats.token_rewards;
+                rewards.unpaid_amount = U128(rewards.unpaid_amount.0.saturating_sub(record.amount.0));
+                rewards.paid_amount = U128(rewards.paid_amount.0 + record.amount.0);
+            }
+            RewardType::NFT { .. } => {
+                let rewards = &mut self.stats.nft_rewards;
+                rewards.unpaid_count = rewards.unpaid_count.saturating_sub(1);
+                rewards.paid_count += 1;
+            }
+        }
+    }
+
+    /// Appends a donation to the ledger and indexes it by donor and by donation type.
+    fn add_donation_record(&mut self, mut record: DonationRecord) -> u64 {
+        let id = self.donations.len();
+        record.id = id;
+        self.donations.push(&record);
+        let donor = &record.donor;
+        let donation_type = &record.donation_type;
+
+        // Anonymous donations stay out of the per-account indexes, which are public.
+        if !record.anonymous {
+            let mut by_donor = self
+                .donations_by_donor
+                .get(donor)
+                .unwrap_or_else(|| Vector::new(nested_prefix(b"o", donor)));
+            by_donor.push(&id);
+            self.donations_by_donor.insert(donor, &by_donor);
+        }
+
+        if record.payer != record.donor && !record.anonymous {
+            let mut by_payer = self
+                .gifts_by_payer
+                .get(&record.payer)
+                .unwrap_or_else(|| Vector::new(nested_prefix(b"dp", &record.payer)));
+            by_payer.push(&id);
+            self.gifts_by_payer.insert(&record.payer, &by_payer);
+        }
+
+        let mut by_type = self
+            .donations_by_type
+            .get(donation_type)
+            .unwrap_or_else(|| Vector::new(nested_prefix(b"y", donation_type)));
+        by_type.push(&id);
+        self.donations_by_type.insert(donation_type, &by_type);
+        id
+    }
+
+    fn paginate_donation_ids(&self, ids: Option<Vector<u64>>, start: u64, limit: u64) -> PaginatedDonations {
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let Some(ids) = ids else {
+            return PaginatedDonations { donations: vec![], has_more: false };
+        };
+        let donations: Vec<DonationRecord> = ids
+            .iter()
+            .skip(start as usize)
+            .take(limit as usize)
+            .filter_map(|id| self.donations.get(id))
+            .map(DonationRecord::public_view)
+            .collect();
+        let has_more = ids.len() > start + limit;
+        PaginatedDonations { donations, has_more }
+    }
+
+    #[payable]
+    pub fn log_airdrop(&mut self, recipient: AccountId, channel_id: String, donation_type: DonationType, amount: U128) {
+        self.assert_admin();
+        let input = AirdropInput {
+            recipient,
+            channel_id,
+            donation_type,
+            amount,
+            deposit: U128(env::attached_deposit().as_yoctonear()),
+        };
+        let reward_type = self.check_airdrop(&input).unwrap_or_else(|err| env::panic_str(&err));
+        self.apply_airdrop(input, reward_type);
+    }
+
+    /// Logs many airdrops in one call. Each entry's `deposit` must be covered by the attached
+    /// deposit. Entries are validated one by one: invalid ones are rejected with a reason and the
+    /// rest are logged. Processing stops when gas runs low, and `next_index` tells the caller
+    /// where to resume. Deposits of entries that were not logged are refunded.
+    #[payable]
+    pub fn log_airdrops(&mut self, entries: Vec<AirdropInput>) -> LogAirdropsResult {
+        self.assert_admin();
+        assert!(
+            !entries.is_empty() && entries.len() <= MAX_AIRDROP_BATCH,
+            "Batch must have between 1 and {} entries",
+            MAX_AIRDROP_BATCH
+        );
+        let attached_amount = env::attached_deposit().as_yoctonear();
+        let total_deposit = entries
+            .iter()
+            .try_fold(0u128, |total, entry| total.checked_add(entry.deposit.0))
+            .unwrap_or_else(|| env::panic_str("Entry deposits overflow"));
+        assert!(attached_amount >= total_deposit, "Attached deposit must cover the deposits of all entries");
+
+        let (mut applied, mut rejected, mut used_deposit) = (vec![], vec![], 0);
+        let mut next_index = 0;
+        for (index, entry) in entries.into_iter().enumerate() {
+            if env::prepaid_gas().saturating_sub(env::used_gas()) < LOG_AIRDROP_ENTRY_GAS {
+                break;
+            }
+            next_index = index as u64 + 1;
+            match self.check_airdrop(&entry) {
+                Ok(reward_type) => {
+                    used_deposit += entry.deposit.0;
+                    self.apply_airdrop(entry, reward_type);
+                    applied.push(index as u64);
+                }
+                Err(reason) => rejected.push(AirdropRejection { index: index as u64, reason }),
+            }
+        }
+
+        let refunded = attached_amount - used_deposit;
+        if refunded > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(NearToken::from_yoctonear(refunded));
+        }
+        emit_event(
+            "airdrops_logged",
+            json!({
+                "applied": applied.len(),
+                "rejected": rejected.len(),
+                "next_index": next_index,
+                "refunded": U128(refunded),
+            }),
+        );
+        LogAirdropsResult { applied, rejected, next_index, refunded: U128(refunded) }
+    }
+
+    /// Validates an airdrop entry without panicking and returns the reward it would create.
+    fn check_airdrop(&mut self, input: &AirdropInput) -> Result<RewardType, String> {
+        self.check_donation_type(&input.donation_type)?;
+        self.check_eligible(&input.recipient, &input.donation_type, true)?;
+        let reward_type = if input.channel_id.is_empty() {
+            RewardType::Token
+        } else {
+            RewardType::NFT {
+                channel_id: input.channel_id.clone(),
+                token_id: String::new(),
+            }
+        };
+        self.check_campaign_budget(&input.recipient, &input.donation_type, &reward_type, input.amount.0)?;
+        // Last, as flag mode marks the donor.
+        self.check_human(&input.recipient)?;
+        Ok(reward_type)
+    }
+
+    /// Why a reward for `account_id` cannot be paid yet but may be later: the registry rejects
+    /// unverified accounts and has no current answer for it, or the campaign budget is spent.
+    fn reward_pending(&self, account_id: &AccountId, donation_type: &DonationType) -> Option<String> {
+        if let DonationType::Campaign { campaign_id } = donation_type {
+            let campaign = self.campaigns.get(campaign_id)?;
+            if campaign.tokens_allocated.0 >= campaign.budget.0 {
+                return Some("Campaign token budget is spent".to_string());
+            }
+        }
+        let registry = self.human_registry.as_ref().filter(|r| r.mode == HumanCheckMode::Reject)?;
+        let answered = self
+            .human_checks
+            .get(account_id)
+            .is_some_and(|check| env::block_timestamp() < check.checked_at.saturating_add(registry.cache_ttl_ns));
+        (!answered).then(|| format!("No current human check for {}, call verify_human", account_id))
+    }
+
+    /// Checks the recipient of a computed or distributed reward against the campaign rules and
+    /// the human registry.
+    fn check_reward_recipient(&mut self, account_id: &AccountId, donation_type: &DonationType) -> Result<(), String> {
+        self.check_eligible(account_id, donation_type, true)?;
+        // Last, as flag mode marks the donor.
+        self.check_human(account_id)
+    }
+
+    fn apply_airdrop(&mut self, input: AirdropInput, reward_type: RewardType) {
+        let AirdropInput { recipient, donation_type, amount, deposit, .. } = input;
+        let (amount_u128, deposit) = (amount.0, deposit.0);
+        self.add_airdrop(&recipient, reward_type, &donation_type, amount_u128, None);
+        let mut donor = self.donors.get(&recipient).unwrap();
+        donor.add_public_donation_type(&donation_type);
+        if deposit > 0 {
+            donor.donation_amount = U128(donor.donation_amount.0 + deposit);
+        }
+        self.donors.insert(&recipient, &donor);
+        if deposit > 0 {
+            self.record_donation_stats(&donation_type, &None, deposit);
+            let donation_id = self.add_donation_record(DonationRecord {
+                id: 0,
+                donor: recipient.clone(),
+                payer: env::predecessor_account_id(),
+                token_id: None,
+                amount: U128(deposit),
+                timestamp: env::block_timestamp(),
+                donation_type: donation_type.clone(),
+                memo: None,
+                memo_hidden: false,
+                anonymous: false,
+                status: DonationStatus::Retained,
+                protocol_fee: U128(0),
+                referrer_id: None,
+                referral_fee: U128(0),
+                net_amount: U128(deposit),
+                refundable_until: None,
+                rewarded: false,
+                matched_amount: U128(0),
+                matches: vec![],
+                streak: 0,
+                refunded_at: None,
+            });
+            self.adjust_window_totals(&self.donations.get(donation_id).unwrap(), false);
+        }
+
+        log!("Logged airdrop for {}: {} tokens, donation_type {:?}", recipient, amount_u128, donation_type);
+    }
+
+    /// Appends an airdrop record and credits it to the recipient's donor entry and the stats.
+    fn add_airdrop(
+        &mut self,
+        recipient: &AccountId,
+        reward_type: RewardType,
+        donation_type: &DonationType,
+        amount: u128,
+        donation_id: Option<u64>,
+    ) {
+        self.airdrop_records.push(&AirdropRecord {
+            recipient: recipient.clone(),
+            amount: U128(amount),
+            timestamp: env::block_timestamp(),
+            paid: false,
+            reward_type: reward_type.clone(),
+            donation_type: donation_type.clone(),
+            donation_id,
+        });
+
+        let mut donor = self.donors.get(recipient).unwrap_or_else(|| Donor::new(recipient.clone()));
+        donor.airdrop_amount = U128(donor.airdrop_amount.0 + amount);
+
+        // Add donation_type if not already present
+        if !donor.donation_types.contains(donation_type) {
+            donor.donation_types.push(donation_type.clone());
+        }
+
+        // Add reward_type if not already present
+        if !donor.reward_types.contains(&reward_type) {
+            donor.reward_types.push(reward_type.clone());
+        }
+
+        self.donors.insert(recipient, &donor);
+        self.total_distributed += amount;
+        self.record_airdrop_stats(donation_type, &reward_type, amount);
+        if let DonationType::Campaign { campaign_id } = donation_type {
+            let mut campaign = self.campaigns.get(campaign_id).unwrap();
+            let key = (campaign_id.clone(), recipient.clone());
+            let mut rewards = self.campaign_rewards.get(&key).unwrap_or_default();
+            rewards.record_count += 1;
+            match reward_type {
+                RewardType::Token => {
+                    let overflow = || env::panic_str("Campaign token allocation overflows");
+                    rewards.tokens = U128(rewards.tokens.0.checked_add(amount).unwrap_or_else(overflow));
+                    campaign.tokens_allocated = U128(campaign.tokens_allocated.0.checked_add(amount).unwrap_or_else(overflow));
+                }
+                RewardType::NFT { .. } => {
+                    rewards.nfts += 1;
+                    campaign.nfts_allocated += 1;
+                }
+            }
+            self.campaign_rewards.insert(&key, &rewards);
+            self.campaigns.insert(campaign_id, &campaign);
+        }
+    }
+
+    /// Starts a snapshot of every donor's cumulative NEAR donations per donation type as of
+    /// now. Fill it in with `build_snapshot` until it reports `complete`.
+    pub fn take_snapshot(&mut self, label: String) -> Snapshot {
+        self.assert_admin();
+        let snapshot = Snapshot {
+            id: self.snapshots.len(),
+            label,
+            taken_at: env::block_timestamp(),
+            block_height: env::block_height(),
+            donation_cutoff: self.donations.len(),
+            cursor: 0,
+            donor_count: 0,
+            total_donated: U128(0),
+            complete: self.donations.is_empty(),
+        };
+        self.snapshots.push(&snapshot);
+        emit_event(
+            "snapshot_taken",
+            json!({ "snapshot_id": snapshot.id, "label": snapshot.label, "block_height": snapshot.block_height }),
+        );
+        snapshot
+    }
+
+    /// Adds up to `limit` more donations to the snapshot.
+    pub fn build_snapshot(&mut self, snapshot_id: u64, limit: u64) -> Snapshot {
+        self.assert_admin();
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let mut snapshot = self.snapshots.get(snapshot_id).expect("Snapshot not found");
+        assert!(!snapshot.complete, "Snapshot is already complete");
+        let mut donors = self
+            .snapshot_donors
+            .get(&snapshot_id)
+            .unwrap_or_else(|| Vector::new(nested_prefix(b"v", &snapshot_id)));
+
+        let end = snapshot.donation_cutoff.min(snapshot.cursor + limit);
+        for donation in (snapshot.cursor..end).filter_map(|id| self.donations.get(id)) {
+            // Donations refunded before they are reached are left out; `refund_donation` takes
+            // later refunds back out.
+            if !donation.counts_for_distribution() {
+                continue;
+            }
+            let key = (snapshot_id, donation.donor.clone());
+            let mut amounts = match self.snapshot_balances.get(&key) {
+                Some(amounts) => amounts,
+                None => {
+                    donors.push(&donation.donor);
+                    snapshot.donor_count += 1;
+                    vec![]
+                }
+            };
+            match amounts.iter_mut().find(|a| a.donation_type == donation.donation_type) {
+                Some(entry) => entry.amount = U128(entry.amount.0 + donation.amount.0),
+                None => amounts.push(DonationTypeAmount { donation_type: donation.donation_type, amount: donation.amount }),
+            }
+            self.snapshot_balances.insert(&key, &amounts);
+            snapshot.total_donated = U128(snapshot.total_donated.0 + donation.amount.0);
+        }
+        snapshot.cursor = end;
+        snapshot.complete = end == snapshot.donation_cutoff;
+
+        self.snapshot_donors.insert(&snapshot_id, &donors);
+        self.snapshots.replace(snapshot_id, &snapshot);
+        if snapshot.complete {
+            emit_event(
+                "snapshot_completed",
+                json!({ "snapshot_id": snapshot_id, "donor_count": snapshot.donor_count, "total_donated": snapshot.total_donated }),
+            );
+        }
+        snapshot
+    }
+
+    /// Takes a refunded donation out of the snapshots that already counted it, or puts it back
+    /// when the refund fails. Snapshots are taken in order, so only those taken since the
+    /// donation are visited.
+    fn adjust_snapshots(&mut self, donation: &DonationRecord, refunded: bool) {
+        if donation.token_id.is_some() {
+            return;
+        }
+        let amount = donation.amount.0;
+        for snapshot_id in (0..self.snapshots.len()).rev() {
+            let mut snapshot = self.snapshots.get(snapshot_id).unwrap();
+            if snapshot.donation_cutoff <= donation.id {
+                break;
+            }
+            let key = (snapshot_id, donation.donor.clone());
+            let Some(mut amounts) = self.snapshot_balances.get(&key).filter(|_| snapshot.cursor > donation.id) else {
+                continue;
+            };
+            let Some(entry) = amounts.iter_mut().find(|a| a.donation_type == donation.donation_type) else {
+                continue;
+            };
+            entry.amount = U128(if refunded { entry.amount.0.saturating_sub(amount) } else { entry.amount.0 + amount });
+            self.snapshot_balances.insert(&key, &amounts);
+            snapshot.total_donated = U128(if refunded {
+                snapshot.total_donated.0.saturating_sub(amount)
+            } else {
+                snapshot.total_donated.0 + amount
+            });
+            self.snapshots.replace(snapshot_id, &snapshot);
+        }
+    }
+
+    fn snapshot_amount(&self, snapshot_id: u64, account_id: &AccountId, donation_type: &DonationType) -> u128 {
+        self.snapshot_balances
+            .get(&(snapshot_id, account_id.clone()))
+            .and_then(|amounts| amounts.into_iter().find(|a| &a.donation_type == donation_type))
+            .map_or(0, |a| a.amount.0)
+    }
+
+    pub fn set_streak_config(&mut self, config: StreakConfig) {
+        self.assert_admin();
+        assert!(config.period_ns > 0, "Streak period must be greater than 0");
+        assert!(
+            config.multipliers.windows(2).all(|pair| pair[0].min_streak < pair[1].min_streak),
+            "Multipliers must be sorted by ascending min_streak"
+        );
+        assert!(config.multipliers.iter().all(|m| m.multiplier_bps > 0), "Multiplier must be greater than 0");
+        self.streak_config = config;
+        emit_event("streak_config_updated", json!({ "config": self.streak_config }));
+    }
+
+    /// Extends the donor's streak with a donation made at `at` if their last counted donation
+    /// was in the previous period, keeps it within the same or a later period and starts a new
+    /// one otherwise.
+    fn update_streak(&self, donor: &mut Donor, at: u64) {
+        let period_ns = self.streak_config.period_ns;
+        let period = at / period_ns;
+        donor.streak = match donor.last_donation_at.map(|last| last / period_ns) {
+            Some(last) if last >= period => donor.streak,
+            Some(last) if last + 1 == period => donor.streak + 1,
+            _ => 1,
+        };
+        donor.longest_streak = donor.longest_streak.max(donor.streak);
+        donor.last_donation_at = donor.last_donation_at.max(Some(at));
+    }
+
+    /// Counts a donation towards its donor's streak once it can no longer be refunded, so
+    /// refunded donations never extend a streak.
+    fn count_streak(&mut self, donation: &mut DonationRecord) {
+        if donation.streak > 0 {
+            return;
+        }
+        let mut donor = self.donors.get(&donation.donor).expect("Donor not found");
+        self.update_streak(&mut donor, donation.timestamp);
+        donation.streak = donor.streak;
+        self.donors.insert(&donation.donor, &donor);
+    }
+
+    pub fn set_reward_policy(&mut self, donation_type: DonationType, policy: RewardPolicy) {
+        self.assert_admin();
+        self.assert_valid_donation_type(&donation_type);
+        policy.assert_valid();
+        self.reward_policies.insert(&donation_type, &policy);
+        emit_event("reward_policy_set", json!({ "donation_type": donation_type, "policy": policy }));
+    }
+
+    pub fn remove_reward_policy(&mut self, donation_type: DonationType) {
+        self.assert_admin();
+        self.reward_policies.remove(&donation_type);
+        log!("Reward policy removed for {:?}", donation_type);
+    }
+
+    /// Turns up to `limit` settled NEAR donations of `donation_type` into token airdrop records
+    /// using the type's reward policy, continuing from where the last call stopped. Each
+    /// rewarded donation is linked from its record and can no longer be refunded. Stops early at
+    /// a donation still in flight or in its refund window, and at one whose reward is only held
+    /// up for now, as reported in `blocked_by`.
+    pub fn compute_airdrops(&mut self, donation_type: DonationType, limit: u64) -> ComputeAirdropsResult {
+        self.assert_admin();
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let policy = self.reward_policies.get(&donation_type).expect("No reward policy for this donation type");
+        let ids = self.donations_by_type.get(&donation_type);
+        let total = ids.as_ref().map_or(0, |ids| ids.len());
+        let mut cursor = self.reward_cursors.get(&donation_type).unwrap_or(0);
+        let now = env::block_timestamp();
+        let (mut airdrops_created, mut total_amount, mut skipped) = (0, 0, 0);
+        let mut blocked_by = None;
+
+        let end = total.min(cursor + limit);
+        while cursor < end {
+            let donation_id = ids.as_ref().unwrap().get(cursor).unwrap();
+            let mut donation = self.donations.get(donation_id).unwrap();
+            let settled = match donation.status {
+                DonationStatus::Pending => false,
+                DonationStatus::Escrowed => now >= donation.refundable_until.unwrap_or(0),
+                _ => true,
+            };
+            if !settled {
+                break;
+            }
+            // Retained deposits were already rewarded by `log_airdrop`; token amounts are not
+            // comparable with the policy's yoctoNEAR scale.
+            let rewarded_through = self
+                .snapshot_rewarded_through
+                .get(&(donation_type.clone(), donation.donor.clone()))
+                .unwrap_or(0);
+            let rewardable = matches!(donation.status, DonationStatus::Forwarded | DonationStatus::Escrowed)
+                && donation.token_id.is_none()
+                && !donation.rewarded
+                && donation_id >= rewarded_through;
+            if !rewardable {
+                cursor += 1;
+                continue;
+            }
+            // These can clear later, so the donation is kept for the next call.
+            if let Some(reason) = self.reward_pending(&donation.donor, &donation_type) {
+                blocked_by = Some(format!("Donation #{}: {}", donation_id, reason));
+                break;
+            }
+            cursor += 1;
+            // Escrowed donations past their refund window are final too.
+            self.count_streak(&mut donation);
+            self.donations.replace(donation_id, &donation);
+            let multiplier_bps = self.streak_config.multiplier_bps(donation.streak) as u128;
+            let amount = mul_div(policy.reward_for(donation.amount.0), multiplier_bps, BPS_DENOMINATOR);
+            if amount == 0 {
+                continue;
+            }
+            // Rejected by the campaign rules, a registry answer or a filled per-donor cap.
+            let checked = self.check_reward_recipient(&donation.donor, &donation_type).and_then(|()| {
+                match self.campaign_token_room(&donation.donor, &donation_type) {
+                    0 => Err("Per-donor token cap reached".to_string()),
+                    room => Ok(amount.min(room)),
+                }
+            });
+            let amount = match checked {
+                Ok(amount) => amount,
+                Err(reason) => {
+                    log!("Skipping donation #{}: {}", donation_id, reason);
+                    skipped += 1;
+                    continue;
+                }
+            };
+            donation.rewarded = true;
+            self.donations.replace(donation_id, &donation);
+            let total_key = (donation_type.clone(), donation.donor.clone());
+            let counted = self.distributed_contributions.get(&total_key).unwrap_or(0);
+            self.distributed_contributions.insert(&total_key, &(counted + donation.amount.0));
+            self.add_airdrop(&donation.donor, RewardType::Token, &donation_type, amount, Some(donation_id));
+            airdrops_created += 1;
+            total_amount += amount;
+        }
+        self.reward_cursors.insert(&donation_type, &cursor);
+
+        emit_event(
+            "airdrops_computed",
+            json!({
+                "donation_type": donation_type,
+                "airdrops_created": airdrops_created,
+                "total_amount": U128(total_amount),
+                "skipped": skipped,
+                "blocked_by": blocked_by,
+                "next_index": cursor,
+            }),
+        );
+        ComputeAirdropsResult {
+            airdrops_created,
+            total_amount: U128(total_amount),
+            skipped,
+            blocked_by,
+            next_index: cursor,
+            remaining: total - cursor,
+        }
+    }
+
+    /// Starts splitting `budget` reward tokens across the donors of `donation_type` by the
+    /// square root of what each gave. Only NEAR donations made before this call and after the
+    /// previous round count, and those can no longer be refunded. A new round can start once
+    /// the previous one is complete. Run `process_distribution` until it reports `Complete`.
+    pub fn start_quadratic_distribution(&mut self, donation_type: DonationType, budget: U128) -> Distribution {
+        self.start_distribution(donation_type, budget, DistributionMode::Quadratic, None)
+    }
+
+    /// Like `start_quadratic_distribution` or `finalize_round`, but reads each donor's total from
+    /// a completed snapshot so donations made after it cannot change the allocation.
+    pub fn start_snapshot_distribution(
+        &mut self,
+        snapshot_id: u64,
+        donation_type: DonationType,
+        budget: U128,
+        mode: DistributionMode,
+    ) -> Distribution {
+        let snapshot = self.snapshots.get(snapshot_id).expect("Snapshot not found");
+        assert!(snapshot.complete, "Snapshot is still being built");
+        self.start_distribution(donation_type, budget, mode, Some(snapshot_id))
+    }
+
+    /// Finalizes the current round of a pot, campaign or project by splitting `budget` reward
+    /// tokens across the donors since the previous round in proportion to what each gave.
+    /// Rounds are numbered from 0. Large rounds take several calls with the same arguments; each
+    /// one picks up from the stored cursor. A finalized round cannot be finalized again, so the
+    /// next call needs donations made since.
+    pub fn finalize_round(&mut self, donation_type: DonationType, budget: U128) -> Distribution {
+        match self.distributions.get(&donation_type) {
+            Some(distribution) if distribution.status != DistributionStatus::Complete => {
+                assert!(
+                    distribution.mode == DistributionMode::ProRata
+                        && distribution.budget == budget
+                        && distribution.snapshot_id.is_none(),
+                    "Round is already being finalized with different parameters"
+                );
+            }
+            latest => {
+                if let Some(latest) = latest {
+                    let donation_count = self.donations_by_type.get(&donation_type).map_or(0, |ids| ids.len());
+                    assert!(
+                        self.type_position(&donation_type, latest.donation_id_cutoff) < donation_count,
+                        "Round has already been finalized"
+                    );
+                }
+                self.start_distribution(donation_type.clone(), budget, DistributionMode::ProRata, None);
+            }
+        }
+        self.process_distribution(donation_type, FINALIZE_ROUND_BATCH)
+    }
+
+    /// Number of donations of `donation_type` with an id below `donation_id`. Ids only grow, so
+    /// this is a position in `donations_by_type`.
+    fn type_position(&self, donation_type: &DonationType, donation_id: u64) -> u64 {
+        let Some(ids) = self.donations_by_type.get(donation_type) else {
+            return 0;
+        };
+        let (mut low, mut high) = (0, ids.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if ids.get(mid).unwrap() < donation_id {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    fn start_distribution(
+        &mut self,
+        donation_type: DonationType,
+        budget: U128,
+        mode: DistributionMode,
+        snapshot_id: Option<u64>,
+    ) -> Distribution {
+        self.assert_admin();
+        self.assert_valid_donation_type(&donation_type);
+        assert!(budget.0 > 0, "Budget must be greater than 0");
+        if let DonationType::Campaign { campaign_id } = &donation_type {
+            let campaign = self.campaigns.get(campaign_id).unwrap();
+            let remaining = campaign.budget.0.saturating_sub(campaign.tokens_allocated.0);
+            assert!(
+                budget.0 <= remaining,
+                "Distribution budget exceeds the campaign token budget, {} remaining",
+                remaining
+            );
+        }
+        let previous = self.distributions.get(&donation_type);
+        if let Some(previous) = &previous {
+            assert!(
+                previous.status == DistributionStatus::Complete,
+                "A distribution for this donation type is still running"
+            );
+        }
+        let snapshot = snapshot_id.map(|snapshot_id| self.snapshots.get(snapshot_id).unwrap());
+        let donation_id_cutoff = snapshot.as_ref().map_or(self.donations.len(), |snapshot| snapshot.donation_cutoff);
+        let previous_cutoff = previous.as_ref().map_or(0, |previous| previous.donation_id_cutoff);
+        let donation_start = self.type_position(&donation_type, previous_cutoff);
+        let type_cutoff = self.type_position(&donation_type, donation_id_cutoff);
+        assert!(
+            previous.is_none() || (donation_id_cutoff >= previous_cutoff && type_cutoff > donation_start),
+            "Rewards for this donation type have already been distributed"
+        );
+        let round = previous.as_ref().map_or(0, |previous| previous.round + 1);
+        if let Some(previous) = previous {
+            self.past_distributions.insert(&(donation_type.clone(), previous.round), &previous);
+        }
+        let distribution = Distribution {
+            donation_type: donation_type.clone(),
+            round,
+            mode,
+            budget,
+            status: DistributionStatus::Collecting,
+            snapshot_id,
+            donation_start: if snapshot.is_some() { 0 } else { donation_start },
+            donation_cutoff: snapshot.map_or(type_cutoff, |snapshot| snapshot.donor_count),
+            donation_id_cutoff,
+            cursor: if snapshot_id.is_some() { 0 } else { donation_start },
+            donor_count: 0,
+            total_contributed: U128(0),
+            total_weight: U128(0),
+            distributed: U128(0),
+            dust: U128(0),
+            started_at: env::block_timestamp(),
+            completed_at: None,
+        };
+        self.distributions.insert(&donation_type, &distribution);
+        emit_event(
+            "distribution_started",
+            json!({
+                "donation_type": donation_type,
+                "round": distribution.round,
+                "mode": distribution.mode,
+                "budget": budget,
+            }),
+        );
+        distribution
+    }
+
+    /// Advances the distribution of `donation_type` by up to `limit` donations or donors.
+    pub fn process_distribution(&mut self, donation_type: DonationType, limit: u64) -> Distribution {
+        self.assert_admin();
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let mut distribution = self.distributions.get(&donation_type).expect("Distribution not found");
+        let round_key = (donation_type.clone(), distribution.round);
+        let mut donors = self
+            .distribution_donors
+            .get(&round_key)
+            .unwrap_or_else(|| Vector::new(nested_prefix(b"qd", &round_key)));
+
+        let mut steps = 0;
+        while steps < limit && distribution.status != DistributionStatus::Complete {
+            match distribution.status {
+                DistributionStatus::Collecting => {
+                    if distribution.cursor == distribution.donation_cutoff {
+                        distribution.status = DistributionStatus::Weighing;
+                        distribution.cursor = 0;
+                        continue;
+                    }
+                    let (donor_id, amount, donation_id) = match distribution.snapshot_id {
+                        Some(snapshot_id) => {
+                            let donor_id = self.snapshot_donors.get(&snapshot_id).unwrap().get(distribution.cursor).unwrap();
+                            // Snapshot totals are cumulative, so leave out what earlier rounds counted.
+                            let counted = self
+                                .distributed_contributions
+                                .get(&(donation_type.clone(), donor_id.clone()))
+                                .unwrap_or(0);
+                            let amount = self.snapshot_amount(snapshot_id, &donor_id, &donation_type);
+                            (donor_id, amount.saturating_sub(counted), None)
+                        }
+                        None => {
+                            let ids = self.donations_by_type.get(&donation_type).unwrap();
+                            let donation_id = ids.get(distribution.cursor).unwrap();
+                            let donation = self.donations.get(donation_id).unwrap();
+                            // Donations `compute_airdrops` already rewarded are not rewarded again.
+                            let amount = match donation.counts_for_distribution() && !donation.rewarded {
+                                true => donation.amount.0,
+                                false => 0,
+                            };
+                            (donation.donor, amount, Some(donation_id))
+                        }
+                    };
+                    // Donors the campaign rules or the human registry reject are left out of the round.
+                    let amount = match amount {
+                        0 => 0,
+                        _ => match self.check_reward_recipient(&donor_id, &donation_type) {
+                            Ok(()) => amount,
+                            Err(reason) => {
+                                log!("Leaving {} out of the distribution: {}", donor_id, reason);
+                                0
+                            }
+                        },
+                    };
+                    if amount > 0 {
+                        let key = (donation_type.clone(), distribution.round, donor_id.clone());
+                        let contributed = self.distribution_contributions.get(&key);
+                        if contributed.is_none() {
+                            donors.push(&donor_id);
+                            distribution.donor_count += 1;
+                        }
+                        self.distribution_contributions.insert(&key, &(contributed.unwrap_or(0) + amount));
+                        let total_key = (donation_type.clone(), donor_id.clone());
+                        let counted = self.distributed_contributions.get(&total_key).unwrap_or(0);
+                        self.distributed_contributions.insert(&total_key, &(counted + amount));
+                        match donation_id {
+                            Some(donation_id) => {
+                                let mut donation = self.donations.get(donation_id).unwrap();
+                                donation.rewarded = true;
+                                self.donations.replace(donation_id, &donation);
+                            }
+                            None => {
+                                let through = self.snapshot_rewarded_through.get(&total_key).unwrap_or(0);
+                                let through = through.max(distribution.donation_id_cutoff);
+                                self.snapshot_rewarded_through.insert(&total_key, &through);
+                            }
+                        }
+                        distribution.total_contributed = U128(distribution.total_contributed.0 + amount);
+                    }
+                }
+                DistributionStatus::Weighing => {
+                    if distribution.cursor == distribution.donor_count {
+                        distribution.status = DistributionStatus::Allocating;
+                        distribution.cursor = 0;
+                        continue;
+                    }
+                    let donor_id = donors.get(distribution.cursor).unwrap();
+                    let contributed =
+                        self.distribution_contributions.get(&(donation_type.clone(), distribution.round, donor_id)).unwrap();
+                    distribution.total_weight = U128(distribution.total_weight.0 + distribution.mode.weight(contributed));
+                }
+                DistributionStatus::Allocating => {
+                    if distribution.cursor == distribution.donor_count {
+                        distribution.status = DistributionStatus::Complete;
+                        distribution.dust = U128(distribution.budget.0 - distribution.distributed.0);
+                        distribution.completed_at = Some(env::block_timestamp());
+                        emit_event(
+                            "distribution_completed",
+                            json!({
+                                "donation_type": donation_type,
+                                "round": distribution.round,
+                                "donor_count": distribution.donor_count,
+                                "distributed": distribution.distributed,
+                                "dust": distribution.dust,
+                            }),
+                        );
+                        continue;
+                    }
+                    let donor_id = donors.get(distribution.cursor).unwrap();
+                    let contributed = self
+                        .distribution_contributions
+                        .get(&(donation_type.clone(), distribution.round, donor_id.clone()))
+                        .unwrap();
+                    let weight = distribution.mode.weight(contributed);
+                    // Rewards logged since the round started may have used up the campaign budget,
+                    // the donor's token cap or `max_rewards_per_donor`; what no longer fits is left
+                    // in the dust.
+                    let share = mul_div(distribution.budget.0, weight, distribution.total_weight.0)
+                        .min(self.campaign_token_room(&donor_id, &donation_type));
+                    if share > 0 && self.check_eligible(&donor_id, &donation_type, true).is_ok() {
+                        self.add_airdrop(&donor_id, RewardType::Token, &donation_type, share, None);
+                        distribution.distributed = U128(distribution.distributed.0 + share);
+                    }
+                }
+                DistributionStatus::Complete => unreachable!(),
+            }
+            distribution.cursor += 1;
+            steps += 1;
+        }
+
+        self.distribution_donors.insert(&round_key, &donors);
+        self.distributions.insert(&donation_type, &distribution);
+        distribution
+    }
+
+    #[payable]
+    pub fn record_donation(
+        &mut self,
+        donation_type: DonationType,
+        memo: Option<String>,
+        referrer_id: Option<AccountId>,
+        anonymous: Option<bool>,
+    ) -> u64 {
+        let signer = env::predecessor_account_id();
+        self.record_donation_for(signer, donation_type, memo, referrer_id, anonymous)
+    }
+
+    /// Records a donation paid by the caller but credited to `beneficiary`, who gets the donor
+    /// totals and airdrop eligibility. Refunds still go back to the caller.
+    #[payable]
+    pub fn record_donation_for(
+        &mut self,
+        beneficiary: AccountId,
+        donation_type: DonationType,
+        memo: Option<String>,
+        referrer_id: Option<AccountId>,
+        anonymous: Option<bool>,
+    ) -> u64 {
+        let payer = env::predecessor_account_id();
+        let attached_amount = env::attached_deposit().as_yoctonear();
+        assert!(attached_amount > 0, "Attached deposit must be greater than 0");
+        let options = DonationOptions { memo, referrer_id, anonymous: anonymous.unwrap_or(false) };
+        let donation_id = self.internal_record_donation(payer, beneficiary, None, attached_amount, donation_type, options);
+        self.forward_if_not_escrowed(donation_id);
+        donation_id
+    }
+
+    /// NEP-141 receiver: `msg` is an `FtDonationMessage`. The whole amount is kept and forwarded.
+    pub fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
+        let token_id = env::predecessor_account_id();
+        assert!(self.accepted_tokens.contains(&token_id), "Token {} is not accepted for donations", token_id);
+        assert!(amount.0 > 0, "Donation amount must be greater than 0");
+        if let Ok(message) = serde_json::from_str::<FtMatchingPoolMessage>(&msg) {
+            self.internal_create_matching_pool(sender_id, Some(token_id), amount.0, message.matching_pool);
+            return PromiseOrValue::Value(U128(0));
+        }
+        let message: FtDonationMessage = serde_json::from_str(&msg).expect("Invalid donation message");
+
+        let beneficiary = message.beneficiary.unwrap_or_else(|| sender_id.clone());
+        let options = DonationOptions {
+            memo: message.memo,
+            referrer_id: message.referrer_id,
+            anonymous: message.anonymous.unwrap_or(false),
+        };
+        let donation_id =
+            self.internal_record_donation(sender_id, beneficiary, Some(token_id), amount.0, message.donation_type, options);
+        self.forward_if_not_escrowed(donation_id);
+        PromiseOrValue::Value(U128(0))
+    }
+
+    fn internal_record_donation(
+        &mut self,
+        payer: AccountId,
+        donor_id: AccountId,
+        token_id: Option<AccountId>,
+        amount: u128,
+        donation_type: DonationType,
+        options: DonationOptions,
+    ) -> u64 {
+        let DonationOptions { memo, referrer_id, anonymous } = options;
+        self.assert_valid_donation_type(&donation_type);
+        if let Some(referrer_id) = &referrer_id {
+            assert!(referrer_id != &donor_id && referrer_id != &payer, "Donors cannot refer themselves");
+            assert!(referrer_id != &env::current_account_id(), "Invalid referrer_id");
+        }
+        let memo = memo.map(|m| m.trim().to_string()).filter(|m| !m.is_empty());
+        if let Some(memo) = &memo {
+            assert!(memo.len() <= MAX_MEMO_BYTES, "Memo must be {} bytes or less", MAX_MEMO_BYTES);
+        }
+
+        let accepted = self
+            .check_donation(&donor_id, &token_id, amount, &donation_type)
+            .unwrap_or_else(|err| env::panic_str(&err));
+        if accepted < amount {
+            log!("Donation exceeds the campaign cap, refunding {} yoctoNEAR to {}", amount - accepted, payer);
+            Self::transfer_funds(&token_id, payer.clone(), amount - accepted);
+        }
+        let amount = accepted;
+
+        let mut donor = self.donors.get(&donor_id).unwrap_or_else(|| Donor::new(donor_id.clone()));
+        match &token_id {
+            None => donor.donation_amount = U128(donor.donation_amount.0 + amount),
+            Some(token_id) => add_token_amount(&mut donor.ft_donations, token_id, amount),
+        }
+
+        // Add donation_type if not already present
+        if !donor.donation_types.contains(&donation_type) {
+            donor.donation_types.push(donation_type.clone());
+        }
+        self.record_donation_stats(&donation_type, &token_id, amount);
+
+        // Fees come out of the gross amount; only the net is forwarded to the beneficiary.
+        let protocol_fee = amount * self.protocol_fee_bps as u128 / BPS_DENOMINATOR;
+        let referral_fee = if referrer_id.is_some() {
+            amount * self.referral_fee_bps as u128 / BPS_DENOMINATOR
+        } else {
+            0
+        };
+        let net_amount = amount - protocol_fee - referral_fee;
+
+        // With a refund window the funds stay here until `release_donation` forwards them.
+        let (status, refundable_until) = if self.refund_window_ns > 0 {
+            (DonationStatus::Escrowed, Some(env::block_timestamp() + self.refund_window_ns))
+        } else {
+            (DonationStatus::Pending, None)
+        };
+        let record = DonationRecord {
+            id: 0,
+            donor: donor_id.clone(),
+            payer: payer.clone(),
+            token_id: token_id.clone(),
+            amount: U128(amount),
+            timestamp: env::block_timestamp(),
+            donation_type: donation_type.clone(),
+            memo,
+            memo_hidden: false,
+            anonymous,
+            status,
+            protocol_fee: U128(protocol_fee),
+            referrer_id: referrer_id.clone(),
+            referral_fee: U128(referral_fee),
+            net_amount: U128(net_amount),
+            refundable_until,
+            rewarded: false,
+            matched_amount: U128(0),
+            matches: vec![],
+            streak: 0,
+            refunded_at: None,
+        };
+        donor.track_visibility(&record, false);
+        self.donors.insert(&donor_id, &donor);
+        self.apply_fees(&record, false);
+        self.adjust_campaign_raised(&record, false);
+        let donation_id = self.add_donation_record(record);
+        emit_event(
+            "donation_recorded",
+            json!({
+                "donation_id": donation_id,
+                "donor": if anonymous { ANONYMOUS_ACCOUNT_ID.to_string() } else { donor_id.to_string() },
+                "payer": if anonymous { ANONYMOUS_ACCOUNT_ID.to_string() } else { payer.to_string() },
+                "token_id": token_id,
+                "donation_type": donation_type,
+                "referrer_id": referrer_id,
+                "gross_amount": U128(amount),
+                "protocol_fee": U128(protocol_fee),
+                "referral_fee": U128(referral_fee),
+                "net_amount": U128(net_amount),
+            }),
+        );
+        log!(
+            "Recorded donation #{} of {} {} for {}, donation_type {:?}",
+            donation_id,
+            amount,
+            token_id.as_ref().map(|t| t.to_string()).unwrap_or("yoctoNEAR".to_string()),
+            if anonymous { ANONYMOUS_ACCOUNT_ID } else { donor_id.as_str() },
+            donation_type
+        );
+        donation_id
+    }
+
+    /// Books the donation's fees on the payer and referrer fee accounts, or takes them back
+    /// off again when `reverse` is set.
+    fn apply_fees(&mut self, donation: &DonationRecord, reverse: bool) {
+        let adjust = |total: U128, amount: u128| {
+            U128(if reverse { total.0.saturating_sub(amount) } else { total.0 + amount })
+        };
+        let adjust_token = |amounts: &mut Vec<TokenAmount>, token_id: &AccountId, amount: u128| {
+            if reverse {
+                sub_token_amount(amounts, token_id, amount)
+            } else {
+                add_token_amount(amounts, token_id, amount)
+            }
+        };
+        let (protocol_fee, referral_fee) = (donation.protocol_fee.0, donation.referral_fee.0);
+
+        let mut donor_fees = self.fee_accounts.get(&donation.payer).unwrap_or_default();
+        match &donation.token_id {
+            None => {
+                donor_fees.protocol_fees_paid = adjust(donor_fees.protocol_fees_paid, protocol_fee);
+                donor_fees.referral_fees_paid = adjust(donor_fees.referral_fees_paid, referral_fee);
+                self.stats.protocol_fees = adjust(self.stats.protocol_fees, protocol_fee);
+                self.stats.referral_fees = adjust(self.stats.referral_fees, referral_fee);
+            }
+            Some(token_id) => adjust_token(&mut donor_fees.ft_fees_paid, token_id, protocol_fee + referral_fee),
+        }
+        self.fee_accounts.insert(&donation.payer, &donor_fees);
+
+        if let Some(referrer_id) = &donation.referrer_id {
+            let mut referrer_fees = self.fee_accounts.get(referrer_id).unwrap_or_default();
+            match &donation.token_id {
+                None => referrer_fees.referral_fees_earned = adjust(referrer_fees.referral_fees_earned, referral_fee),
+                Some(token_id) => adjust_token(&mut referrer_fees.ft_referral_fees_earned, token_id, referral_fee),
+            }
+            referrer_fees.referral_count = if reverse {
+                referrer_fees.referral_count.saturating_sub(1)
+            } else {
+                referrer_fees.referral_count + 1
+            };
+            self.fee_accounts.insert(referrer_id, &referrer_fees);
+        }
+    }
+
+    pub fn set_fees(&mut self, protocol_fee_bps: u32, referral_fee_bps: u32) {
+        self.assert_admin();
+        assert!(protocol_fee_bps <= MAX_PROTOCOL_FEE_BPS, "Protocol fee cannot exceed {} basis points", MAX_PROTOCOL_FEE_BPS);
+        assert!(referral_fee_bps <= MAX_REFERRAL_FEE_BPS, "Referral fee cannot exceed {} basis points", MAX_REFERRAL_FEE_BPS);
+        self.protocol_fee_bps = protocol_fee_bps;
+        self.referral_fee_bps = referral_fee_bps;
+        emit_event("fees_updated", json!({ "protocol_fee_bps": protocol_fee_bps, "referral_fee_bps": referral_fee_bps }));
+        log!("Fees set to {} bps protocol, {} bps referral", protocol_fee_bps, referral_fee_bps);
+    }
+
+    /// Sends NEAR, or the donated NEP-141 token when `token_id` is set.
+    fn transfer_funds(token_id: &Option<AccountId>, receiver_id: AccountId, amount: u128) -> Promise {
+        match token_id {
+            None => Promise::new(receiver_id).transfer(NearToken::from_yoctonear(amount)),
+            Some(token_id) => Promise::new(token_id.clone()).function_call(
+                "ft_transfer".to_string(),
+                json!({ "receiver_id": receiver_id, "amount": U128(amount) })
+                    .to_string()
+                    .into_bytes(),
+                NearToken::from_yoctonear(1),
+                Gas::from_tgas(30),
+            ),
+        }
+    }
+
+    pub fn add_accepted_token(&mut self, token_id: AccountId) {
+        self.assert_admin();
+        assert!(!self.accepted_tokens.contains(&token_id), "Token already accepted");
+        self.accepted_tokens.push(token_id.clone());
+        log!("Accepting donations in {}", token_id);
+    }
+
+    pub fn remove_accepted_token(&mut self, token_id: AccountId) {
+        self.assert_admin();
+        self.accepted_tokens.retain(|t| t != &token_id);
+        log!("No longer accepting donations in {}", token_id);
+    }
+
+    /// The account that receives donations of the given type.
+    fn resolve_beneficiary(&self, donation_type: &DonationType) -> AccountId {
+        match donation_type {
+            DonationType::Pot { pot_id } => pot_id.clone(),
+            DonationType::Campaign { campaign_id } => self.campaigns.get(campaign_id).expect("Campaign not found").owner,
+            DonationType::Project { project_id } => self.projects.get(project_id).expect("Project not found").beneficiary,
+            DonationType::Direct => self.treasury.clone(),
+        }
+    }
+
+    fn forward_if_not_escrowed(&mut self, donation_id: u64) {
+        if self.donations.get(donation_id).unwrap().status == DonationStatus::Pending {
+            self.forward_donation(donation_id);
+        }
+    }
+
+    /// Forwards an escrowed donation once its refund window has passed. Anyone can call it.
+    pub fn release_donation(&mut self, donation_id: u64) -> Promise {
+        let mut donation = self.donations.get(donation_id).expect("Donation not found");
+        assert_eq!(donation.status, DonationStatus::Escrowed, "Donation is not in escrow");
+        assert!(
+            env::block_timestamp() >= donation.refundable_until.unwrap_or(0),
+            "Refund window has not ended yet"
+        );
+        donation.status = DonationStatus::Pending;
+        self.donations.replace(donation_id, &donation);
+        self.forward_donation(donation_id)
+    }
+
+    fn forward_donation(&mut self, donation_id: u64) -> Promise {
+        let mut donation = self.donations.get(donation_id).expect("Donation not found");
+        self.draw_matches(&mut donation);
+        self.donations.replace(donation_id, &donation);
+        let beneficiary = self.resolve_beneficiary(&donation.donation_type);
+        let mut fees = vec![];
+        if donation.protocol_fee.0 > 0 {
+            fees.push((self.treasury.clone(), donation.protocol_fee));
+        }
+        if let Some(referrer_id) = donation.referrer_id.clone().filter(|_| donation.referral_fee.0 > 0) {
+            fees.push((referrer_id, donation.referral_fee));
+        }
+        log!("Forwarding donation #{} of {} to {}", donation_id, donation.net_amount.0, beneficiary);
+
+        let mut transfers = Self::transfer_funds(&donation.token_id, beneficiary.clone(), donation.net_amount.0);
+        for (receiver_id, amount) in &fees {
+            transfers = transfers.and(Self::transfer_funds(&donation.token_id, receiver_id.clone(), amount.0));
+        }
+        // Matches are paid from the callback, which then needs room for a transfer and its callback.
+        let callback_gas = if donation.matched_amount.0 > 0 { 60 } else { 10 };
+        transfers.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(Gas::from_tgas(callback_gas))
+                .on_donation_forwarded(donation_id, beneficiary, fees),
+        )
+    }
+
+    /// Settles a forward: the first promise result is the transfer to the beneficiary, followed
+    /// by one per entry of `fees`. Fees that could not be sent are kept for their recipient to
+    /// withdraw with `withdraw_fees`.
+    #[private]
+    pub fn on_donation_forwarded(&mut self, donation_id: u64, beneficiary: AccountId, fees: Vec<(AccountId, U128)>) {
+        let mut donation = self.donations.get(donation_id).expect("Donation not found");
+        let forwarded = env::promise_results_count() == 1 + fees.len() as u64
+            && matches!(env::promise_result(0), PromiseResult::Successful(_));
+        for (index, (receiver_id, amount)) in fees.into_iter().enumerate() {
+            if !matches!(env::promise_result(index as u64 + 1), PromiseResult::Successful(_)) {
+                self.credit_unclaimed_fees(&receiver_id, &donation.token_id, amount.0);
+            }
+        }
+
+        if forwarded {
+            donation.status = DonationStatus::Forwarded;
+            self.count_streak(&mut donation);
+            emit_event(
+                "donation_forwarded",
+                json!({ "donation_id": donation_id, "beneficiary": beneficiary, "amount": donation.amount }),
+            );
+            log!("Forwarded donation #{} to {}", donation_id, beneficiary);
+            self.pay_matches(&donation, beneficiary);
+        } else {
+            donation.status = DonationStatus::Held;
+            self.return_matches(&mut donation);
+            emit_event(
+                "donation_held",
+                json!({ "donation_id": donation_id, "beneficiary": beneficiary, "amount": donation.amount }),
+            );
+            log!("Forwarding donation #{} to {} failed, holding funds for refund", donation_id, beneficiary);
+        }
+        self.donations.replace(donation_id, &donation);
+    }
+
+    fn credit_unclaimed_fees(&mut self, account_id: &AccountId, token_id: &Option<AccountId>, amount: u128) {
+        let mut unclaimed = self.unclaimed_fees.get(account_id).unwrap_or_default();
+        match token_id {
+            None => unclaimed.near = U128(unclaimed.near.0 + amount),
+            Some(token_id) => add_token_amount(&mut unclaimed.tokens, token_id, amount),
+        }
+        self.unclaimed_fees.insert(account_id, &unclaimed);
+        emit_event(
+            "fee_unclaimed",
+            json!({ "account_id": account_id, "token_id": token_id, "amount": U128(amount) }),
+        );
+    }
+
+    /// Sends the caller the fees in NEAR, or in `token_id`, that could not be transferred to
+    /// them when donations were forwarded, for example before they registered with the token.
+    pub fn withdraw_fees(&mut self, token_id: Option<AccountId>) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let mut unclaimed = self.unclaimed_fees.get(&account_id).unwrap_or_default();
+        let amount = match &token_id {
+            None => std::mem::take(&mut unclaimed.near).0,
+            Some(token_id) => {
+                let amount = unclaimed.tokens.iter().find(|t| &t.token_id == token_id).map_or(0, |t| t.amount.0);
+                unclaimed.tokens.retain(|t| &t.token_id != token_id);
+                amount
+            }
+        };
+        assert!(amount > 0, "Nothing to withdraw");
+        self.unclaimed_fees.insert(&account_id, &unclaimed);
+        log!("Withdrawing {} in unclaimed fees to {}", amount, account_id);
+
+        Self::transfer_funds(&token_id, account_id.clone(), amount).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(Gas::from_tgas(5))
+                .on_fees_withdrawn(account_id, token_id, U128(amount)),
+        )
+    }
+
+    #[private]
+    pub fn on_fees_withdrawn(&mut self, account_id: AccountId, token_id: Option<AccountId>, amount: U128) {
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            self.credit_unclaimed_fees(&account_id, &token_id, amount.0);
+            log!("Fee withdrawal to {} failed, {} restored", account_id, amount.0);
+        }
+    }
+
+    /// Returns a donation whose forwarding failed to the payer and reverses its accounting.
+    pub fn refund_held_donation(&mut self, donation_id: u64) -> Promise {
+        let mut donation = self.donations.get(donation_id).expect("Donation not found");
+        assert_eq!(env::predecessor_account_id(), donation.payer, "Only the donor can reclaim a donation");
+        assert_eq!(donation.status, DonationStatus::Held, "Donation is not held for refund");
+        // Escrowed donations can be rewarded once their window ends, before they are released.
+        assert!(!donation.rewarded, "Donation has already been used for an airdrop");
+
+        donation.status = DonationStatus::Refunded;
+        donation.refunded_at = Some(env::block_timestamp());
+        self.donations.replace(donation_id, &donation);
+        self.reverse_donation_accounting(&donation);
+        // Fees were paid out when the donation was forwarded, so only the net amount comes back.
+        log!("Refunding held donation #{} of {} to {}", donation_id, donation.net_amount.0, donation.clone().public_view().payer);
+
+        Self::transfer_funds(&donation.token_id, donation.payer.clone(), donation.net_amount.0)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(10))
+                    .on_donation_refunded(donation_id, DonationStatus::Held),
+            )
+    }
+
+    /// Returns an escrowed donation to the payer while its refund window is open.
+    pub fn refund_donation(&mut self, donation_id: u64) -> Promise {
+        let mut donation = self.donations.get(donation_id).expect("Donation not found");
+        assert_eq!(env::predecessor_account_id(), donation.payer, "Only the donor can refund a donation");
+        assert_eq!(donation.status, DonationStatus::Escrowed, "Donation has already been forwarded");
+        assert!(
+            env::block_timestamp() < donation.refundable_until.unwrap_or(0),
+            "Refund window has ended"
+        );
+        assert!(!donation.rewarded, "Donation has already been used for an airdrop");
+        // Rounds only move forward, so the latest one covers every counted donation.
+        assert!(
+            self.distributions
+                .get(&donation.donation_type)
+                .is_none_or(|distribution| donation.id >= distribution.donation_id_cutoff),
+            "Rewards for this donation type have already been distributed"
+        );
+
+        donation.status = DonationStatus::Refunded;
+        donation.refunded_at = Some(env::block_timestamp());
+        self.donations.replace(donation_id, &donation);
+        self.reverse_donation_accounting(&donation);
+        self.apply_fees(&donation, true);
+        self.adjust_snapshots(&donation, true);
+        log!("Refunding donation #{} of {} to {}", donation_id, donation.amount.0, donation.clone().public_view().payer);
+
+        Self::transfer_funds(&donation.token_id, donation.payer.clone(), donation.amount.0)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(10))
+                    .on_donation_refunded(donation_id, DonationStatus::Escrowed),
+            )
+    }
+
+    pub fn add_moderator(&mut self, account_id: AccountId) {
+        self.assert_admin();
+        assert!(!self.moderators.contains(&account_id), "Account is already a moderator");
+        self.moderators.push(account_id.clone());
+        log!("Added moderator {}", account_id);
+    }
+
+    pub fn remove_moderator(&mut self, account_id: AccountId) {
+        self.assert_admin();
+        self.moderators.retain(|m| m != &account_id);
+        log!("Removed moderator {}", account_id);
+    }
+
+    fn assert_moderator(&self) {
+        let caller = env::predecessor_account_id();
+        assert!(caller == self.admin || self.moderators.contains(&caller), "Only admin or moderators can call this function");
+    }
+
+    /// Hides a donation's memo from every view. The donation itself is untouched.
+    pub fn hide_memo(&mut self, donation_id: u64, reason: Option<String>) {
+        self.assert_moderator();
+        self.set_memo_hidden(donation_id, true, reason);
+    }
+
+    pub fn unhide_memo(&mut self, donation_id: u64) {
+        self.assert_moderator();
+        self.set_memo_hidden(donation_id, false, None);
+    }
+
+    fn set_memo_hidden(&mut self, donation_id: u64, hidden: bool, reason: Option<String>) {
+        let mut donation = self.donations.get(donation_id).expect("Donation not found");
+        assert!(donation.memo.is_some(), "Donation has no memo");
+        donation.memo_hidden = hidden;
+        self.donations.replace(donation_id, &donation);
+        emit_event(
+            if hidden { "memo_hidden" } else { "memo_unhidden" },
+            json!({ "donation_id": donation_id, "moderator": env::predecessor_account_id(), "reason": reason }),
+        );
+        log!("Memo of donation #{} hidden: {}", donation_id, hidden);
+    }
+
+    pub fn set_refund_window(&mut self, refund_window_ns: u64) {
+        self.assert_admin();
+        self.refund_window_ns = refund_window_ns;
+        log!("Refund window set to {} ns", refund_window_ns);
+    }
+
+    /// Rolls a refund back to `previous_status` when the transfer to the donor failed.
+    #[private]
+    pub fn on_donation_refunded(&mut self, donation_id: u64, previous_status: DonationStatus) {
+        let mut donation = self.donations.get(donation_id).expect("Donation not found");
+        let refunded = env::promise_results_count() == 1
+            && matches!(env::promise_result(0), PromiseResult::Successful(_));
+        // Anonymous donors stay redacted in events and logs, as in the donation views.
+        let shown = donation.clone().public_view();
+
+        if refunded {
+            emit_event(
+                "donation_refunded",
+                json!({ "donation_id": donation_id, "donor": shown.donor, "payer": shown.payer, "amount": donation.amount }),
+            );
+            log!("Refunded donation #{} to {}", donation_id, shown.payer);
+        } else {
+            // Escrowed donations had their fees reversed too; held ones had already paid them.
+            if previous_status == DonationStatus::Escrowed {
+                self.apply_fees(&donation, false);
+                self.adjust_snapshots(&donation, false);
+            }
+            donation.status = previous_status;
+            donation.refunded_at = None;
+            self.donations.replace(donation_id, &donation);
+            self.restore_donation_accounting(&donation);
+            log!("Refund of donation #{} to {} failed, donation restored", donation_id, shown.payer);
+        }
+    }
+
+    fn reverse_donation_accounting(&mut self, donation: &DonationRecord) {
+        let amount = donation.amount.0;
+        let mut donor = self.donors.get(&donation.donor).expect("Donor not found");
+        match &donation.token_id {
+            None => donor.donation_amount = U128(donor.donation_amount.0.saturating_sub(amount)),
+            Some(token_id) => sub_token_amount(&mut donor.ft_donations, token_id, amount),
+        }
+        donor.track_visibility(donation, true);
+        self.donors.insert(&donation.donor, &donor);
+
+        match &donation.token_id {
+            None => self.stats.total_donated = U128(self.stats.total_donated.0.saturating_sub(amount)),
+            Some(token_id) => sub_token_amount(&mut self.stats.ft_donated, token_id, amount),
+        }
+        self.stats.donation_count = self.stats.donation_count.saturating_sub(1);
+        self.stats.by_donation_type.get_mut(&donation.donation_type).remove_donation(&donation.token_id, amount);
+
+        let mut type_stats = self.donation_type_stats.get(&donation.donation_type).unwrap_or_default();
+        type_stats.remove_donation(&donation.token_id, amount);
+        self.donation_type_stats.insert(&donation.donation_type, &type_stats);
+        self.adjust_campaign_raised(donation, true);
+    }
+
+    fn restore_donation_accounting(&mut self, donation: &DonationRecord) {
+        let mut donor = self.donors.get(&donation.donor).expect("Donor not found");
+        match &donation.token_id {
+            None => donor.donation_amount = U128(donor.donation_amount.0 + donation.amount.0),
+            Some(token_id) => add_token_amount(&mut donor.ft_donations, token_id, donation.amount.0),
+        }
+        donor.track_visibility(donation, false);
+        self.donors.insert(&donation.donor, &donor);
+        self.record_donation_stats(&donation.donation_type, &donation.token_id, donation.amount.0);
+        self.adjust_campaign_raised(donation, false);
+    }
+
+    /// Sets or, with `None`, removes the human registry that reward recipients are checked against.
+    pub fn set_human_registry(&mut self, registry: Option<HumanRegistry>) {
+        self.assert_admin();
+        if let Some(registry) = &registry {
+            assert!(!registry.method_name.is_empty(), "Method name must not be empty");
+        }
+        self.human_registry = registry;
+        log!("Human registry set to {:?}", self.human_registry.as_ref().map(|r| r.contract_id.to_string()));
+    }
+
+    /// Asks the human registry about `account_id` and caches the answer. Anyone can call it.
+    pub fn verify_human(&mut self, account_id: AccountId) -> Promise {
+        let registry = self.human_registry.as_ref().expect("No human registry configured");
+        Promise::new(registry.contract_id.clone())
+            .function_call(
+                registry.method_name.clone(),
+                json!({ "account_id": account_id }).to_string().into_bytes(),
+                NearToken::from_yoctonear(0),
+                Gas::from_tgas(10),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(10))
+                    .on_human_check(account_id),
+            )
+    }
+
+    #[private]
+    pub fn on_human_check(&mut self, account_id: AccountId) -> bool {
+        let is_human = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<bool>(&value).unwrap_or(false),
+            _ => {
+                log!("Human registry check for {} failed", account_id);
+                return false;
+            }
+        };
+        self.human_checks.insert(&account_id, &HumanCheck { is_human, checked_at: env::block_timestamp() });
+        if is_human {
+            if let Some(mut donor) = self.donors.get(&account_id).filter(|d| d.unverified) {
+                donor.unverified = false;
+                self.donors.insert(&account_id, &donor);
+            }
+        }
+        log!("Human registry reports {} as human: {}", account_id, is_human);
+        is_human
+    }
+
+    /// Whether `account_id` has a cached positive registry answer that has not expired. Always
+    /// true when no registry is configured.
+    fn is_verified_human(&self, account_id: &AccountId) -> bool {
+        let Some(registry) = &self.human_registry else {
+            return true;
+        };
+        self.human_checks.get(account_id).is_some_and(|check| {
+            check.is_human && env::block_timestamp() < check.checked_at.saturating_add(registry.cache_ttl_ns)
+        })
+    }
+
+    /// Rejects or flags `account_id` according to the registry mode unless it is a verified human.
+    fn enforce_human(&mut self, account_id: &AccountId) {
+        self.check_human(account_id).unwrap_or_else(|err| env::panic_str(&err));
+    }
+
+    fn check_human(&mut self, account_id: &AccountId) -> Result<(), String> {
+        if self.is_verified_human(account_id) {
+            return Ok(());
+        }
+        match self.human_registry.as_ref().unwrap().mode {
+            HumanCheckMode::Reject => Err("Account is not a verified human, call verify_human first".to_string()),
+            HumanCheckMode::Flag => {
+                let mut donor = self.donors.get(account_id).unwrap_or_else(|| Donor::new(account_id.clone()));
+                if !donor.unverified {
+                    donor.unverified = true;
+                    self.donors.insert(account_id, &donor);
+                    emit_event("donor_flagged", json!({ "account_id": account_id, "reason": "unverified" }));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Adds the attached NEAR to the caller's prepaid balance for recurring donations.
+    #[payable]
+    pub fn deposit_prepaid(&mut self) -> U128 {
+        let attached_amount = env::attached_deposit().as_yoctonear();
+        assert!(attached_amount > 0, "Attached deposit must be greater than 0");
+        U128(self.add_prepaid_balance(&env::predecessor_account_id(), attached_amount))
+    }
+
+    fn add_prepaid_balance(&mut self, account_id: &AccountId, amount: u128) -> u128 {
+        let balance = self.prepaid_balances.get(account_id).unwrap_or(0) + amount;
+        self.prepaid_balances.insert(account_id, &balance);
+        balance
+    }
+
+    /// Schedules a donation of `amount` from the caller's prepaid balance every `interval_ns`,
+    /// with the first one due immediately. Any attached NEAR is added to the balance.
+    #[payable]
+    pub fn create_recurring_donation(
+        &mut self,
+        amount: U128,
+        interval_ns: u64,
+        donation_type: DonationType,
+    ) -> RecurringDonation {
+        assert!(amount.0 > 0, "Amount must be greater than 0");
+        assert!(
+            interval_ns > 0 && interval_ns <= MAX_RECURRING_INTERVAL_NS,
+            "Interval must be between 1 and {} nanoseconds",
+            MAX_RECURRING_INTERVAL_NS
+        );
+        let next_due = env::block_timestamp();
+        assert!(next_due.checked_add(interval_ns).is_some(), "Interval is too long");
+        self.assert_valid_donation_type(&donation_type);
+        let donor = env::predecessor_account_id();
+        let attached_amount = env::attached_deposit().as_yoctonear();
+        if attached_amount > 0 {
+            self.add_prepaid_balance(&donor, attached_amount);
+        }
+
+        let recurring = RecurringDonation {
+            id: self.next_recurring_id,
+            donor,
+            amount,
+            interval_ns,
+            donation_type,
+            next_due,
+            executed_count: 0,
+            skipped_count: 0,
+            last_donation_id: None,
+        };
+        self.next_recurring_id += 1;
+        self.recurring_donations.insert(&recurring.id, &recurring);
+        emit_event(
+            "recurring_donation_created",
+            json!({
+                "recurring_id": recurring.id,
+                "donor": recurring.donor,
+                "amount": recurring.amount,
+                "interval_ns": recurring.interval_ns,
+                "donation_type": recurring.donation_type,
+            }),
+        );
+        recurring
+    }
+
+    /// Stops a recurring donation. The prepaid balance stays available to `withdraw_prepaid`.
+    pub fn cancel_recurring_donation(&mut self, recurring_id: u64) {
+        let recurring = self.recurring_donations.get(&recurring_id).expect("Recurring donation not found");
+        assert_eq!(env::predecessor_account_id(), recurring.donor, "Only the donor can cancel a recurring donation");
+        self.recurring_donations.remove(&recurring_id);
+        emit_event("recurring_donation_cancelled", json!({ "recurring_id": recurring_id, "donor": recurring.donor }));
+    }
+
+    /// Withdraws `amount`, or the whole unused prepaid balance, back to the caller.
+    pub fn withdraw_prepaid(&mut self, amount: Option<U128>) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let balance = self.prepaid_balances.get(&account_id).unwrap_or(0);
+        let amount = amount.map(|a| a.0).unwrap_or(balance);
+        assert!(amount > 0, "Nothing to withdraw");
+        assert!(amount <= balance, "Amount exceeds the prepaid balance of {}", balance);
+
+        self.prepaid_balances.insert(&account_id, &(balance - amount));
+        log!("Withdrawing {} yoctoNEAR of prepaid balance to {}", amount, account_id);
+        Promise::new(account_id.clone())
+            .transfer(NearToken::from_yoctonear(amount))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(5))
+                    .on_prepaid_withdrawn(account_id, U128(amount)),
+            )
+    }
+
+    #[private]
+    pub fn on_prepaid_withdrawn(&mut self, account_id: AccountId, amount: U128) {
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            self.add_prepaid_balance(&account_id, amount.0);
+            log!("Withdrawal to {} failed, {} yoctoNEAR restored to the prepaid balance", account_id, amount.0);
+        }
+    }
+
+    /// Executes up to `limit` recurring donations that are due, continuing from where the last
+    /// call stopped. Anyone can call it. Returns how many donations were made.
+    pub fn process_recurring(&mut self, limit: u64) -> u64 {
+        assert!(
+            limit > 0 && limit <= MAX_RECURRING_BATCH,
+            "Limit must be between 1 and {}",
+            MAX_RECURRING_BATCH
+        );
+        let len = self.recurring_donations.len();
+        let now = env::block_timestamp();
+        let mut executed = 0;
+        for _ in 0..limit.min(len) {
+            let recurring_id = self.recurring_donations.keys_as_vector().get(self.recurring_cursor % len).unwrap();
+            let mut recurring = self.recurring_donations.get(&recurring_id).unwrap();
+            // A due schedule waits for the next call when its forward may not fit in the gas left.
+            if recurring.next_due <= now && env::prepaid_gas().saturating_sub(env::used_gas()) < RECURRING_DONATION_GAS {
+                break;
+            }
+            self.recurring_cursor = (self.recurring_cursor + 1) % len;
+            if recurring.next_due > now {
+                continue;
+            }
+            // Missed periods are skipped rather than paid out all at once.
+            // Schedules that cannot advance any further are parked at the end of time.
+            let missed = (now - recurring.next_due) / recurring.interval_ns + 1;
+            recurring.next_due = missed
+                .checked_mul(recurring.interval_ns)
+                .and_then(|step| recurring.next_due.checked_add(step))
+                .unwrap_or(u64::MAX);
+
+            let balance = self.prepaid_balances.get(&recurring.donor).unwrap_or(0);
+            let checked = if balance < recurring.amount.0 {
+                Err("Insufficient prepaid balance".to_string())
+            } else {
+                self.check_donation(&recurring.donor, &None, recurring.amount.0, &recurring.donation_type)
+            };
+            match checked {
+                Ok(accepted) => {
+                    self.prepaid_balances.insert(&recurring.donor, &(balance - accepted));
+                    let donation_id = self.internal_record_donation(
+                        recurring.donor.clone(),
+                        recurring.donor.clone(),
+                        None,
+                        accepted,
+                        recurring.donation_type.clone(),
+                        DonationOptions::default(),
+                    );
+                    self.forward_if_not_escrowed(donation_id);
+                    recurring.executed_count += 1;
+                    recurring.last_donation_id = Some(donation_id);
+                    executed += 1;
+                }
+                Err(err) => {
+                    recurring.skipped_count += 1;
+                    log!("Skipped recurring donation #{}: {}", recurring_id, err);
+                }
+            }
+            self.recurring_donations.insert(&recurring_id, &recurring);
+        }
+        executed
+    }
+
+    /// Opens a matching pool funded with the attached NEAR. Qualifying donations of
+    /// `donation_type` draw `ratio_bps` of their amount from it, up to `per_donor_cap` per donor.
+    /// Token pools are funded through `ft_transfer_call` with an `FtMatchingPoolMessage`.
+    #[payable]
+    pub fn create_matching_pool(
+        &mut self,
+        donation_type: DonationType,
+        ratio_bps: u32,
+        per_donor_cap: Option<U128>,
+    ) -> MatchingPool {
+        let attached_amount = env::attached_deposit().as_yoctonear();
+        assert!(attached_amount > 0, "Attached deposit must be greater than 0");
+        self.internal_create_matching_pool(
+            env::predecessor_account_id(),
+            None,
+            attached_amount,
+            MatchingPoolArgs { donation_type, ratio_bps, per_donor_cap },
+        )
+    }
+
+    fn internal_create_matching_pool(
+        &mut self,
+        sponsor: AccountId,
+        token_id: Option<AccountId>,
+        amount: u128,
+        args: MatchingPoolArgs,
+    ) -> MatchingPool {
+        assert!(args.ratio_bps > 0, "Match ratio must be greater than 0");
+        self.assert_valid_donation_type(&args.donation_type);
+        let open_pools = self.open_matching_pools.get(&(args.donation_type.clone(), token_id.clone())).unwrap_or_default();
+        assert!(
+            open_pools.len() < MAX_OPEN_MATCHING_POOLS,
+            "This donation type already has {} open matching pools",
+            MAX_OPEN_MATCHING_POOLS
+        );
+        let pool = MatchingPool {
+            id: self.next_matching_pool_id,
+            sponsor,
+            donation_type: args.donation_type,
+            token_id,
+            ratio_bps: args.ratio_bps,
+            per_donor_cap: args.per_donor_cap,
+            deposited: U128(amount),
+            balance: U128(amount),
+            matched: U128(0),
+            created_at: env::block_timestamp(),
+            closed: false,
+        };
+        self.next_matching_pool_id += 1;
+        self.matching_pools.insert(&pool.id, &pool);
+        self.index_open_pool(&pool);
+        emit_event(
+            "matching_pool_created",
+            json!({
+                "pool_id": pool.id,
+                "sponsor": pool.sponsor,
+                "donation_type": pool.donation_type,
+                "token_id": pool.token_id,
+                "amount": pool.deposited,
+                "ratio_bps": pool.ratio_bps,
+            }),
+        );
+        pool
+    }
+
+    /// Closes a pool and returns its undrawn balance to the sponsor. Matches already drawn by
+    /// in-flight donations are still paid out.
+    pub fn close_matching_pool(&mut self, pool_id: u64) {
+        let mut pool = self.matching_pools.get(&pool_id).expect("Matching pool not found");
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == pool.sponsor || caller == self.admin,
+            "Only the sponsor or admin can close a matching pool"
+        );
+        assert!(!pool.closed, "Matching pool is already closed");
+
+        let refund = pool.balance.0;
+        pool.closed = true;
+        pool.balance = U128(0);
+        self.matching_pools.insert(&pool_id, &pool);
+        self.unindex_open_pool(&pool);
+        if refund > 0 {
+            Self::transfer_funds(&pool.token_id, pool.sponsor.clone(), refund);
+        }
+        emit_event(
+            "matching_pool_closed",
+            json!({ "pool_id": pool_id, "sponsor": pool.sponsor, "refunded": U128(refund) }),
+        );
+    }
+
+    /// Tracks pools that still have a balance, so donations only visit pools they can draw from.
+    fn index_open_pool(&mut self, pool: &MatchingPool) {
+        let key = (pool.donation_type.clone(), pool.token_id.clone());
+        let mut pool_ids = self.open_matching_pools.get(&key).unwrap_or_default();
+        if !pool_ids.contains(&pool.id) {
+            pool_ids.push(pool.id);
+            self.open_matching_pools.insert(&key, &pool_ids);
+        }
+    }
+
+    fn unindex_open_pool(&mut self, pool: &MatchingPool) {
+        let key = (pool.donation_type.clone(), pool.token_id.clone());
+        let mut pool_ids = self.open_matching_pools.get(&key).unwrap_or_default();
+        pool_ids.retain(|pool_id| *pool_id != pool.id);
+        if pool_ids.is_empty() {
+            self.open_matching_pools.remove(&key);
+        } else {
+            self.open_matching_pools.insert(&key, &pool_ids);
+        }
+    }
+
+    /// Reserves a match from every open pool for this donation's type and token.
+    fn draw_matches(&mut self, donation: &mut DonationRecord) {
+        let pool_ids = self
+            .open_matching_pools
+            .get(&(donation.donation_type.clone(), donation.token_id.clone()))
+            .unwrap_or_default();
+        for pool_id in pool_ids {
+            let mut pool = self.matching_pools.get(&pool_id).unwrap();
+            let key = (pool_id, donation.donor.clone());
+            let matched_for_donor = self.matching_donor_totals.get(&key).unwrap_or(0);
+            let mut amount = (donation.amount.0 * pool.ratio_bps as u128 / BPS_DENOMINATOR).min(pool.balance.0);
+            if let Some(cap) = pool.per_donor_cap {
+                amount = amount.min(cap.0.saturating_sub(matched_for_donor));
+            }
+            if amount == 0 {
+                continue;
+            }
+            pool.balance = U128(pool.balance.0 - amount);
+            self.matching_pools.insert(&pool_id, &pool);
+            if pool.balance.0 == 0 {
+                self.unindex_open_pool(&pool);
+            }
+            self.matching_donor_totals.insert(&key, &(matched_for_donor + amount));
+            donation.matches.push(MatchContribution { pool_id, amount: U128(amount) });
+            donation.matched_amount = U128(donation.matched_amount.0 + amount);
+        }
+        if donation.matched_amount.0 > 0 {
+            self.adjust_donor_matched(donation, false);
+        }
+    }
+
+    fn pay_matches(&mut self, donation: &DonationRecord, beneficiary: AccountId) {
+        if donation.matched_amount.0 == 0 {
+            return;
+        }
+        Self::transfer_funds(&donation.token_id, beneficiary.clone(), donation.matched_amount.0).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(Gas::from_tgas(10))
+                .on_matches_paid(donation.id, beneficiary),
+        );
+    }
+
+    /// Books the matches of a forwarded donation once they reach the beneficiary. Matches that
+    /// could not be sent go back to their pools.
+    #[private]
+    pub fn on_matches_paid(&mut self, donation_id: u64, beneficiary: AccountId) {
+        let mut donation = self.donations.get(donation_id).expect("Donation not found");
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            let amount = donation.matched_amount;
+            self.return_matches(&mut donation);
+            self.donations.replace(donation_id, &donation);
+            emit_event(
+                "match_failed",
+                json!({ "donation_id": donation_id, "beneficiary": beneficiary, "amount": amount }),
+            );
+            log!("Paying the match of donation #{} to {} failed, returned to the pools", donation_id, beneficiary);
+            return;
+        }
+        for contribution in &donation.matches {
+            let mut pool = self.matching_pools.get(&contribution.pool_id).unwrap();
+            pool.matched = U128(pool.matched.0 + contribution.amount.0);
+            self.matching_pools.insert(&contribution.pool_id, &pool);
+
+            let mut history = self
+                .matching_history
+                .get(&contribution.pool_id)
+                .unwrap_or_else(|| Vector::new(nested_prefix(b"h", &contribution.pool_id)));
+            history.push(&MatchRecord {
+                donation_id: donation.id,
+                amount: contribution.amount,
+                timestamp: env::block_timestamp(),
+            });
+            self.matching_history.insert(&contribution.pool_id, &history);
+        }
+        emit_event(
+            "donation_matched",
+            json!({
+                "donation_id": donation.id,
+                "beneficiary": beneficiary,
+                "amount": donation.matched_amount,
+                "pools": donation.matches.iter().map(|c| c.pool_id).collect::<Vec<_>>(),
+            }),
+        );
+    }
+
+    /// Gives back the matches of a donation that could not be forwarded. Pools closed in the
+    /// meantime send their share straight back to the sponsor.
+    fn return_matches(&mut self, donation: &mut DonationRecord) {
+        if donation.matched_amount.0 == 0 {
+            return;
+        }
+        self.adjust_donor_matched(donation, true);
+        for contribution in donation.matches.drain(..) {
+            let mut pool = self.matching_pools.get(&contribution.pool_id).unwrap();
+            let key = (contribution.pool_id, donation.donor.clone());
+            let matched_for_donor = self.matching_donor_totals.get(&key).unwrap_or(0);
+            self.matching_donor_totals.insert(&key, &matched_for_donor.saturating_sub(contribution.amount.0));
+            if pool.closed {
+                Self::transfer_funds(&pool.token_id, pool.sponsor.clone(), contribution.amount.0);
+            } else {
+                pool.balance = U128(pool.balance.0 + contribution.amount.0);
+                self.matching_pools.insert(&contribution.pool_id, &pool);
+                self.index_open_pool(&pool);
+            }
+        }
+        donation.matched_amount = U128(0);
+    }
+
+    fn adjust_donor_matched(&mut self, donation: &DonationRecord, reverse: bool) {
+        let amount = donation.matched_amount.0;
+        let mut donor = self.donors.get(&donation.donor).expect("Donor not found");
+        match (&donation.token_id, reverse) {
+            (None, false) => donor.matched_amount = U128(donor.matched_amount.0 + amount),
+            (None, true) => donor.matched_amount = U128(donor.matched_amount.0.saturating_sub(amount)),
+            (Some(token_id), false) => add_token_amount(&mut donor.ft_matched, token_id, amount),
+            (Some(token_id), true) => sub_token_amount(&mut donor.ft_matched, token_id, amount),
+        }
+        self.donors.insert(&donation.donor, &donor);
+    }
+
+    #[payable]
+    pub fn send_nft_reward(&mut self) -> Promise {
+        let signer = env::predecessor_account_id();
+        self.enforce_human(&signer);
+        let donor = self.donors.get(&signer).expect("Donor not found");
+        assert!(!donor.paid, "Payout already completed");
+        self.assert_eligible_to_claim(&donor);
+
+        let channel_id = donor
+            .reward_types
+            .iter()
+            .find_map(|r| match r {
+                RewardType::NFT { channel_id, .. } => Some(channel_id.clone()),
+                _ => None,
+            })
+            .expect("No NFT reward type found for donor");
+
+        log!("Initiating NFT mint for {}", signer);
+
+        Promise::new(self.potlock_nfts_contract.clone())
+            .function_call(
+                "nft_mint".to_string(),
+                json!({
+                    "receiver_id": signer,
+                    "channel_id": channel_id,
+                    "proof": None::<String>,
+                })
+                .to_string()
+                .into_bytes(),
+                env::attached_deposit(),
+                Gas::from_tgas(120)
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(10))
+                    .on_nft_mint_callback(signer)
+            )
+    }
+
+    // #[payable]
+    // pub fn send_token_reward(&mut self) -> Promise {
+    //     let signer = env::predecessor_account_id();
+    //     let donor = self.donors.get(&signer).expect("Donor not found");
+    //     assert!(!donor.paid, "Payout already completed");
+    //     assert!(
+    //         donor.reward_types.contains(&RewardType::Token),
+    //         "Donor reward type does not include Token"
+    //     );
+    //     assert!(donor.airdrop_amount.0 > 0, "No tokens to payout");
+
+    //     log!("Initiating token transfer of {} for {}", donor.airdrop_amount.0, signer);
+
+    //     Promise::new(self.token_contract.clone())
+    //         .function_call(
+    //             "ft_transfer".to_string(),
+    //             json!({
+    //                 "receiver_id": signer,
+    //                 "amount": donor.airdrop_amount,
+    //             })
+    //             .to_string()
+    //             .into_bytes(),
+    //             NearToken::from_yoctonear(1), // Standard 1 yoctoNEAR for FT transfer
+    //             Gas::from_tgas(50)
+    //         )
+    //         .then(
+    //             Self::ext(env::current_account_id())
+    //                 .with_static_gas(Gas::from_tgas(10))
+    //                 .on_token_transfer_callback(signer, donor.airdrop_amount)
+    //         )
+    // }
+
+
+
+    #[payable]
+    pub fn send_token_reward(&mut self) -> Promise {
+        let signer = env::predecessor_account_id();
+        self.enforce_human(&signer);
+        let donor = self.donors.get(&signer).expect("Donor not found");
+        assert!(!donor.paid, "Payout already completed");
+        self.assert_eligible_to_claim(&donor);
+        assert!(
+            donor.reward_types.contains(&RewardType::Token),
+            "Donor reward type does not include Token"
+        );
+        assert!(donor.airdrop_amount.0 > 0, "No tokens to payout");
+
+        log!("Initiating token reward process for {}", signer);
+
+        Promise::new(self.token_contract.clone())
+            .function_call(
+                "storage_balance_of".to_string(),
+                json!({ "account_id": signer })
+                    .to_string()
+                    .into_bytes(),
+                NearToken::from_yoctonear(0),
+                Gas::from_tgas(20),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(80))
+                    .on_storage_check_callback(
+                        signer.clone(),
+                        donor.airdrop_amount,
+                        env::attached_deposit(),
+                    ),
+            )
+    }
+
+    #[private]
+    pub fn on_storage_check_callback(
+        &mut self,
+        signer: AccountId,
+        amount: U128,
+        attached_deposit: NearToken,
+    ) -> Promise {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Expected one promise result"
+        );
+
+        match env::promise_result(0) {
+            PromiseResult::Successful(result) => {
+                let balance: Value = serde_json::from_slice(&result)
+                    .expect("Failed to parse storage_balance_of result");
+
+                if balance != Value::Null {
+                    log!("Account {} is registered, proceeding with transfer", signer);
+                    self.perform_ft_transfer(signer, amount)
+                } else {
+                    log!("Account {} is not registered, registering now", signer);
+                    let storage_deposit_amount = NearToken::from_millinear(1250);
+                    assert!(
+                        attached_deposit >= storage_deposit_amount,
+                        "Insufficient deposit for storage registration, need at least 0.00125 NEAR"
+                    );
+
+                    Promise::new(self.token_contract.clone())
+                        .function_call(
+                            "storage_deposit".to_string(),
+                            json!({ "account_id": signer, "registration_only": true })
+                                .to_string()
+                                .into_bytes(),
+                            storage_deposit_amount,
+                            Gas::from_tgas(30),
+                        )
+                        .then(
+                            Self::ext(env::current_account_id())
+                                .with_static_gas(Gas::from_tgas(60))
+                                .on_storage_deposit_callback(signer, amount),
+                        )
+                }
+            }
+            PromiseResult::Failed => {
+                log!("Failed to check storage balance for {}", signer);
+                panic!("Storage balance check failed");
+            }
+        }
+    }
+
+    #[private]
+    pub fn on_storage_deposit_callback(&mut self, signer: AccountId, amount: U128) -> Promise {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Expected one promise result"
+        );
+
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                log!("Successfully registered {} with token contract", signer);
+                self.perform_ft_transfer(signer, amount)
+            }
+            PromiseResult::Failed => {
+                log!("Failed to register {} with token contract", signer);
+                panic!("Storage deposit failed");
+            }
+        }
+    }
+
+
+    fn perform_ft_transfer(&self, receiver_id: AccountId, amount: U128) -> Promise {
+        log!(
+            "Initiating token transfer of {} for {}",
+            amount.0,
+            receiver_id
+        );
+
+        Promise::new(self.token_contract.clone())
+            .function_call(
+                "ft_transfer".to_string(),
+                json!({
+                    "receiver_id": receiver_id,
+                    "amount": amount,
+                })
+                .to_string()
+                .into_bytes(),
+                NearToken::from_yoctonear(1),
+                Gas::from_tgas(50),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_tgas(10))
+                    .on_token_transfer_callback(receiver_id, amount),
+            )
+    }
+
+    #[private]
+    pub fn on_nft_mint_callback(&mut self, donor_id: AccountId) {
+        if env::promise_results_count() != 1 {
+            log!("Unexpected number of promise results");
+            return;
+        }
+
+        let donor = self.donors.get(&donor_id).expect("Donor not found");
+        let channel_id = donor
+            .reward_types
+            .iter()
+            .find_map(|r| match r {
+                RewardType::NFT { channel_id, .. } => Some(channel_id.clone()),
+                _ => None,
+            })
+            .expect("No NFT reward type");
+
+        match env::promise_result(0) {
+            PromiseResult::Successful(result) => {
+                let token_id = String::from_utf8_lossy(&result).to_string();
+                let new_reward_type = RewardType::NFT {
+                    channel_id: channel_id.clone(),
+                    token_id: token_id.clone(),
+                };
+
+                for i in 0..self.airdrop_records.len() {
+                    let mut record = self.airdrop_records.get(i).unwrap();
+                    if
+                        record.recipient == donor_id &&
+                        matches!(record.reward_type, RewardType::NFT { .. }) &&
+                        !record.paid
+                    {
+                        record.reward_type = new_reward_type.clone();
+                        record.paid = true;
+                        self.airdrop_records.replace(i, &record);
+                        self.record_payout_stats(&record);
+
+                        let mut donor = donor.clone();
+                        // Update reward_types to include the new token_id
+                        if let Some(index) = donor.reward_types.iter().position(|r| matches!(r, RewardType::NFT { channel_id: c, .. } if c == &channel_id)) {
+                            donor.reward_types[index] = new_reward_type.clone();
+                        } else {
+                            donor.reward_types.push(new_reward_type);
+                        }
+                        donor.paid = true;
+                        self.donors.insert(&donor_id, &donor);
+
+                        log!(
+                            "Successfully updated airdrop record for donor {} with NFT token ID {} for donation_type {}",
+                            donor_id,
+                            token_id,
+                            donor.donation_types.iter().last().map(|d| format!("{:?}", d)).unwrap_or_default()
+                        );
+                        return;
+                    }
+                }
+
+                log!("No matching airdrop record found for donor {}", donor_id);
+            }
+            PromiseResult::Failed => {
+                log!("NFT mint failed for donor {}", donor_id);
+            }
+        }
+    }
+
+    #[private]
+    pub fn on_token_transfer_callback(&mut self, donor_id: AccountId, amount: U128) {
+        if env::promise_results_count() != 1 {
+            log!("Unexpected number of promise results");
+            return;
+        }
+
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                let mut donor = self.donors.get(&donor_id).expect("Donor not found");
+                for i in 0..self.airdrop_records.len() {
+                    let mut record = self.airdrop_records.get(i).unwrap();
+                    if
+                        record.recipient == donor_id &&
+                        record.amount == amount &&
+                        matches!(record.reward_type, RewardType::Token) &&
+                        !record.paid
+                    {
+                        record.paid = true;
+                        self.airdrop_records.replace(i, &record);
+                        self.record_payout_stats(&record);
+
+                        donor.paid = true;
+                        self.donors.insert(&donor_id, &donor);
+
+                        log!(
+                            "Successfully transferred {} tokens to donor {} for donation_type {}",
+                            amount.0,
+                            donor_id,
+                            donor.donation_types.iter().last().map(|d| format!("{:?}", d)).unwrap_or_default()
+                        );
+                        return;
+                    }
+                }
+
+                log!("No matching airdrop record found for donor {}", donor_id);
+            }
+            PromiseResult::Failed => {
+                log!("Token transfer failed for donor {}", donor_id);
+            }
+        }
+    }
+
+    pub fn mark_payout_complete(&mut self, donor_id: AccountId) {
+        self.assert_admin();
+        let mut donor = self.donors.get(&donor_id).expect("Donor not found");
         assert!(!donor.paid, "Payout already completed");
+        donor.paid = true;
+        self.donors.insert(&donor_id, &donor);
+
+        for i in 0..self.airdrop_records.len() {
+            let mut record = self.airdrop_records.get(i).unwrap();
+            if record.recipient == donor_id && !record.paid {
+                record.paid = true;
+                self.airdrop_records.replace(i, &record);
+                self.record_payout_stats(&record);
+                break;
+            }
+        }
+        log!("Marked payout complete for donor {}", donor_id);
+    }
+
+    #[payable]
+    pub fn select_nft_reward(&mut self, channel_id: String, donation_type: DonationType) {
+        let signer = env::predecessor_account_id();
+        let mut donor = self.donors.get(&signer).expect("Donor not found");
         assert!(
             donor.reward_types.contains(&RewardType::Token),
             "Donor reward type does not include Token"
         );
-        assert!(donor.airdrop_amount.0 > 0, "No tokens to payout");
+        assert!(!donor.paid, "Payout already completed");
+        self.assert_valid_donation_type(&donation_type);
+
+        let new_reward_type = RewardType::NFT {
+            channel_id: channel_id.clone(),
+            token_id: String::new(),
+        };
+
+        // Add donation_type if not already present
+        if !donor.donation_types.contains(&donation_type) {
+            donor.donation_types.push(donation_type.clone());
+        }
+        donor.add_public_donation_type(&donation_type);
+
+        // Add reward_type if not already present
+        if !donor.reward_types.contains(&new_reward_type) {
+            donor.reward_types.push(new_reward_type);
+        }
+
+        self.donors.insert(&signer, &donor);
+        log!("Donor {} selected NFT reward with channel_id {} for donation_type {:?}", signer, channel_id, donation_type);
+    }
+
+    /// Folds fully paid airdrop records in `[cursor, cursor + limit)` into per-donation-type
+    /// summaries and the running hash commitment, then deletes them. Deleted slots are
+    /// refilled with the last record, so the index order of `get_airdrop_records` changes.
+    /// The storage released by the deleted records is sent to the treasury.
+    pub fn archive_records(&mut self, cursor: u64, limit: u64) -> ArchiveResult {
+        self.assert_admin();
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let storage_before = env::storage_usage();
+
+        let mut index = cursor;
+        let mut scanned = 0;
+        let (mut archived, mut token_amount, mut nft_count) = (0u64, 0u128, 0u32);
+        while index < self.airdrop_records.len() && scanned < limit {
+            scanned += 1;
+            let record = self.airdrop_records.get(index).unwrap();
+            if !record.paid {
+                index += 1;
+                continue;
+            }
+
+            let mut summary = self.archive_summaries.get(&record.donation_type).unwrap_or(ArchiveSummary {
+                donation_type: record.donation_type.clone(),
+                record_count: 0,
+                token_amount: U128(0),
+                nft_count: 0,
+                last_archived_at: 0,
+            });
+            summary.record_count += 1;
+            match record.reward_type {
+                RewardType::Token => {
+                    summary.token_amount = U128(summary.token_amount.0 + record.amount.0);
+                    token_amount += record.amount.0;
+                }
+                RewardType::NFT { .. } => {
+                    summary.nft_count += 1;
+                    nft_count += 1;
+                }
+            }
+            summary.last_archived_at = env::block_timestamp();
+            self.archive_summaries.insert(&record.donation_type, &summary);
+
+            let mut preimage = self.archive_commitment.to_vec();
+            preimage.extend(borsh::to_vec(&record).unwrap());
+            self.archive_commitment = env::sha256_array(&preimage);
+            self.archived_count += 1;
+
+            // The last record moves into this slot, so the index is checked again.
+            self.airdrop_records.swap_remove(index);
+            archived += 1;
+        }
+
+        let freed_bytes = storage_before.saturating_sub(env::storage_usage());
+        let refunded = env::storage_byte_cost().as_yoctonear() * freed_bytes as u128;
+        if refunded > 0 {
+            Promise::new(self.treasury.clone()).transfer(NearToken::from_yoctonear(refunded));
+        }
+
+        let done = index >= self.airdrop_records.len();
+        if archived > 0 {
+            // Only totals are logged; one entry per record would not fit NEAR's log limit.
+            emit_event(
+                "airdrop_records_archived",
+                json!({
+                    "from_index": cursor,
+                    "next_cursor": index,
+                    "count": archived,
+                    "token_amount": U128(token_amount),
+                    "nft_count": nft_count,
+                    "commitment": to_hex(&self.archive_commitment),
+                    "freed_bytes": freed_bytes,
+                    "refunded": U128(refunded),
+                }),
+            );
+        }
+        log!("Archived {} airdrop records, freed {} bytes", archived, freed_bytes);
+
+        ArchiveResult {
+            archived,
+            next_cursor: index,
+            done,
+            freed_bytes,
+            refunded: U128(refunded),
+        }
+    }
+
+    pub fn get_archive_status(&self) -> ArchiveStatus {
+        ArchiveStatus {
+            archived_count: self.archived_count,
+            commitment: to_hex(&self.archive_commitment),
+            live_records: self.airdrop_records.len(),
+        }
+    }
+
+    pub fn get_archive_summary(&self, donation_type: DonationType) -> Option<ArchiveSummary> {
+        self.archive_summaries.get(&donation_type)
+    }
+
+    pub fn get_archive_summaries(&self, start: u64, limit: u64) -> PaginatedArchiveSummaries {
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let summaries: Vec<ArchiveSummary> = self.archive_summaries
+            .values()
+            .skip(start as usize)
+            .take(limit as usize)
+            .collect();
+        let has_more = self.archive_summaries.len() > start + limit;
+        PaginatedArchiveSummaries { summaries, has_more }
+    }
+
+    pub fn get_prepaid_balance(&self, account_id: AccountId) -> U128 {
+        U128(self.prepaid_balances.get(&account_id).unwrap_or(0))
+    }
+
+    pub fn get_recurring_donation(&self, recurring_id: u64) -> Option<RecurringDonation> {
+        self.recurring_donations.get(&recurring_id)
+    }
+
+    pub fn get_recurring_donations(&self, start: u64, limit: u64) -> PaginatedRecurringDonations {
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let recurring_donations: Vec<RecurringDonation> = self.recurring_donations
+            .values()
+            .skip(start as usize)
+            .take(limit as usize)
+            .collect();
+        let has_more = self.recurring_donations.len() > start + limit;
+        PaginatedRecurringDonations { recurring_donations, has_more }
+    }
+
+    pub fn get_snapshot(&self, snapshot_id: u64) -> Option<Snapshot> {
+        self.snapshots.get(snapshot_id)
+    }
+
+    pub fn get_snapshot_balance(&self, snapshot_id: u64, account_id: AccountId) -> SnapshotBalance {
+        let amounts = self.snapshot_balances.get(&(snapshot_id, account_id.clone())).unwrap_or_default();
+        SnapshotBalance { account_id, amounts }
+    }
+
+    pub fn get_snapshot_balances(&self, snapshot_id: u64, start: u64, limit: u64) -> PaginatedSnapshotBalances {
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let Some(donors) = self.snapshot_donors.get(&snapshot_id) else {
+            return PaginatedSnapshotBalances { balances: vec![], has_more: false };
+        };
+        let balances: Vec<SnapshotBalance> = donors
+            .iter()
+            .skip(start as usize)
+            .take(limit as usize)
+            .map(|account_id| self.get_snapshot_balance(snapshot_id, account_id))
+            .collect();
+        let has_more = donors.len() > start + limit;
+        PaginatedSnapshotBalances { balances, has_more }
+    }
+
+    pub fn get_streak_config(&self) -> StreakConfig {
+        self.streak_config.clone()
+    }
+
+    pub fn get_donor_streak(&self, account_id: AccountId) -> DonorStreak {
+        let donor = self.donors.get(&account_id).unwrap_or_else(|| Donor::new(account_id.clone()));
+        let period_ns = self.streak_config.period_ns;
+        let current_period = env::block_timestamp() / period_ns;
+        let current_streak = match donor.last_donation_at {
+            Some(at) if at / period_ns + 1 >= current_period => donor.streak,
+            _ => 0,
+        };
+        DonorStreak {
+            current_streak,
+            longest_streak: donor.longest_streak,
+            last_donation_at: donor.last_donation_at,
+            multiplier_bps: self.streak_config.multiplier_bps(current_streak),
+        }
+    }
+
+    /// Previews the token reward `account_id` would earn by donating `hypothetical_amount`
+    /// yoctoNEAR to `donation_type` now, under the current reward policy, streak multipliers,
+    /// campaign caps, eligibility rules and cached human check. Nothing is written.
+    pub fn preview_reward(
+        &self,
+        account_id: AccountId,
+        donation_type: DonationType,
+        hypothetical_amount: U128,
+    ) -> RewardPreview {
+        let mut donor = self.donors.get(&account_id).unwrap_or_else(|| Donor::new(account_id.clone()));
+        self.update_streak(&mut donor, env::block_timestamp());
+        let mut preview = RewardPreview {
+            rejection: None,
+            reward_rejection: None,
+            accepted_amount: U128(0),
+            base_reward: U128(0),
+            streak: donor.streak,
+            multiplier_bps: self.streak_config.multiplier_bps(donor.streak),
+            capped: false,
+            reward: U128(0),
+        };
+        let accepted = match self.check_donation(&account_id, &None, hypothetical_amount.0, &donation_type) {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                preview.rejection = Some(err);
+                return preview;
+            }
+        };
+        preview.accepted_amount = U128(accepted);
+        let Some(policy) = self.reward_policies.get(&donation_type) else {
+            return preview;
+        };
+        if let Err(err) = self.check_eligible(&account_id, &donation_type, true) {
+            preview.reward_rejection = Some(err);
+            return preview;
+        }
+        let rejects_unverified = self.human_registry.as_ref().is_some_and(|r| r.mode == HumanCheckMode::Reject);
+        if rejects_unverified && !self.is_verified_human(&account_id) {
+            preview.reward_rejection = Some("Account is not a verified human, call verify_human first".to_string());
+            return preview;
+        }
+        preview.base_reward = U128(policy.reward_for(accepted));
+        let reward = mul_div(preview.base_reward.0, preview.multiplier_bps as u128, BPS_DENOMINATOR);
+        let room = self.campaign_token_room(&account_id, &donation_type);
+        preview.capped = reward > room;
+        preview.reward = U128(reward.min(room));
+        preview
+    }
+
+    pub fn get_campaign_budget(&self, campaign_id: String) -> CampaignBudget {
+        let campaign = self.campaigns.get(&campaign_id).expect("Campaign not found");
+        Self::campaign_budget(&campaign)
+    }
+
+    pub fn get_campaign_rewards(&self, campaign_id: String, account_id: AccountId) -> CampaignRewards {
+        self.campaign_rewards.get(&(campaign_id, account_id)).unwrap_or_default()
+    }
+
+    pub fn is_eligible(&self, account_id: AccountId, campaign_id: String) -> EligibilityReport {
+        let campaign = self.campaigns.get(&campaign_id).expect("Campaign not found");
+        self.evaluate_eligibility(&account_id, &campaign, true)
+    }
+
+    pub fn get_human_registry(&self) -> Option<HumanRegistry> {
+        self.human_registry.clone()
+    }
+
+    pub fn get_human_check(&self, account_id: AccountId) -> Option<HumanCheck> {
+        self.human_checks.get(&account_id)
+    }
+
+    /// The latest distribution round of `donation_type`.
+    pub fn get_distribution(&self, donation_type: DonationType) -> Option<Distribution> {
+        self.distributions.get(&donation_type)
+    }
+
+    pub fn get_distribution_round(&self, donation_type: DonationType, round: u32) -> Option<Distribution> {
+        match self.distributions.get(&donation_type) {
+            Some(distribution) if distribution.round == round => Some(distribution),
+            _ => self.past_distributions.get(&(donation_type, round)),
+        }
+    }
+
+    pub fn get_reward_policy(&self, donation_type: DonationType) -> Option<RewardPolicy> {
+        self.reward_policies.get(&donation_type)
+    }
+
+    pub fn get_matching_pool(&self, pool_id: u64) -> Option<MatchingPool> {
+        self.matching_pools.get(&pool_id)
+    }
+
+    pub fn get_matching_pools(&self, start: u64, limit: u64) -> PaginatedMatchingPools {
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let pools: Vec<MatchingPool> = self.matching_pools
+            .values()
+            .skip(start as usize)
+            .take(limit as usize)
+            .collect();
+        let has_more = self.matching_pools.len() > start + limit;
+        PaginatedMatchingPools { pools, has_more }
+    }
+
+    pub fn get_matching_history(&self, pool_id: u64, start: u64, limit: u64) -> PaginatedMatchRecords {
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let Some(history) = self.matching_history.get(&pool_id) else {
+            return PaginatedMatchRecords { records: vec![], has_more: false };
+        };
+        let records: Vec<MatchRecord> = history
+            .iter()
+            .skip(start as usize)
+            .take(limit as usize)
+            .collect();
+        let has_more = history.len() > start + limit;
+        PaginatedMatchRecords { records, has_more }
+    }
+
+    pub fn get_campaign_donor_total(&self, campaign_id: String, account_id: AccountId) -> U128 {
+        U128(self.campaign_donor_totals.get(&(campaign_id, account_id)).unwrap_or(0))
+    }
+
+    pub fn get_moderators(&self) -> Vec<AccountId> {
+        self.moderators.clone()
+    }
+
+    pub fn get_refund_window(&self) -> u64 {
+        self.refund_window_ns
+    }
+
+    pub fn get_fee_config(&self) -> FeeConfig {
+        FeeConfig {
+            protocol_fee_bps: self.protocol_fee_bps,
+            referral_fee_bps: self.referral_fee_bps,
+            treasury: self.treasury.clone(),
+        }
+    }
+
+    pub fn get_unclaimed_fees(&self, account_id: AccountId) -> UnclaimedFees {
+        self.unclaimed_fees.get(&account_id).unwrap_or_default()
+    }
+
+    pub fn get_fee_account(&self, account_id: AccountId) -> FeeAccount {
+        self.fee_accounts.get(&account_id).unwrap_or_default()
+    }
+
+    pub fn get_accepted_tokens(&self) -> Vec<AccountId> {
+        self.accepted_tokens.clone()
+    }
+
+    pub fn get_treasury(&self) -> AccountId {
+        self.treasury.clone()
+    }
+
+    pub fn get_donor(&self, wallet_id: AccountId) -> Option<Donor> {
+        self.donors.get(&wallet_id)
+    }
+
+    pub fn get_donors(&self, start: u64, limit: u64) -> PaginatedDonors {
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let donors: Vec<Donor> = self.donors
+            .values()
+            .skip(start as usize)
+            .take(limit as usize)
+            .map(Donor::public_view)
+            .collect();
+        let has_more = self.donors.len() > start + limit;
+        PaginatedDonors { donors, has_more }
+    }
+
+    pub fn get_donors_by_donation_type(&self, donation_type: DonationType, start: u64, limit: u64) -> PaginatedDonors {
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let donors: Vec<Donor> = self.donors
+            .values()
+            .filter(|donor| donor.listed_under(&donation_type))
+            .skip(start as usize)
+            .take(limit as usize)
+            .map(Donor::public_view)
+            .collect();
+        let total_matching = self
+            .donors
+            .values()
+            .filter(|donor| donor.listed_under(&donation_type))
+            .count() as u64;
+        let has_more = total_matching > start + limit;
+        PaginatedDonors { donors, has_more }
+    }
+
+    pub fn get_campaign(&self, campaign_id: String) -> Option<CampaignView> {
+        self.campaigns.get(&campaign_id).map(|campaign| self.campaign_view(campaign))
+    }
+
+    pub fn get_campaigns(&self, start: u64, limit: u64) -> PaginatedCampaigns {
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let campaigns: Vec<CampaignView> = self.campaigns
+            .values()
+            .skip(start as usize)
+            .take(limit as usize)
+            .map(|campaign| self.campaign_view(campaign))
+            .collect();
+        let has_more = self.campaigns.len() > start + limit;
+        PaginatedCampaigns { campaigns, has_more }
+    }
+
+    pub fn get_project(&self, project_id: String) -> Option<Project> {
+        self.projects.get(&project_id)
+    }
+
+    pub fn get_projects(&self, start: u64, limit: u64) -> PaginatedProjects {
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let projects: Vec<Project> = self.projects
+            .values()
+            .skip(start as usize)
+            .take(limit as usize)
+            .collect();
+        let has_more = self.projects.len() > start + limit;
+        PaginatedProjects { projects, has_more }
+    }
+
+    pub fn get_donation(&self, donation_id: u64) -> Option<DonationRecord> {
+        self.donations.get(donation_id).map(DonationRecord::public_view)
+    }
+
+    pub fn get_donations(&self, start: u64, limit: u64) -> PaginatedDonations {
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let donations: Vec<DonationRecord> = self.donations
+            .iter()
+            .skip(start as usize)
+            .take(limit as usize)
+            .map(DonationRecord::public_view)
+            .collect();
+        let has_more = self.donations.len() > start + limit;
+        PaginatedDonations { donations, has_more }
+    }
+
+    pub fn get_donations_by_donor(&self, donor_id: AccountId, start: u64, limit: u64) -> PaginatedDonations {
+        self.paginate_donation_ids(self.donations_by_donor.get(&donor_id), start, limit)
+    }
+
+    /// Gifts paid by `payer_id` on behalf of other donors, oldest first.
+    pub fn get_gifts_by_payer(&self, payer_id: AccountId, start: u64, limit: u64) -> PaginatedDonations {
+        self.paginate_donation_ids(self.gifts_by_payer.get(&payer_id), start, limit)
+    }
+
+    pub fn get_donations_by_donation_type(&self, donation_type: DonationType, start: u64, limit: u64) -> PaginatedDonations {
+        self.paginate_donation_ids(self.donations_by_type.get(&donation_type), start, limit)
+    }
+
+    pub fn get_airdrop_records(&self, start: u64, limit: u64) -> PaginatedAirdropRecords {
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let records: Vec<AirdropRecord> = self.airdrop_records
+            .iter()
+            .skip(start as usize)
+            .take(limit as usize)
+            .collect();
+        let has_more = self.airdrop_records.len() > start + limit;
+        PaginatedAirdropRecords { records, has_more }
+    }
+
+    pub fn get_airdrop_records_by_donation_type(
+        &self,
+        donation_type: DonationType,
+        start: u64,
+        limit: u64
+    ) -> PaginatedAirdropRecords {
+        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
+        let records: Vec<AirdropRecord> = self.airdrop_records
+            .iter()
+            .filter(|record| record.donation_type == donation_type)
+            .skip(start as usize)
+            .take(limit as usize)
+            .collect();
+        let total_matching = self.airdrop_records
+            .iter()
+            .filter(|record| record.donation_type == donation_type)
+            .count() as u64;
+        let has_more = total_matching > start + limit;
+        PaginatedAirdropRecords { records, has_more }
+    }
+
+    /// Returns the NEAR donated to the project and the token rewards logged for it. Until the
+    /// aggregate stats were added this summed the full donation totals of every donor who ever
+    /// gave to the project, and counted the `amount` of NFT airdrops as well; NFT rewards are now
+    /// only counted in `get_donation_type_stats`.
+    pub fn get_project_rewards(&self, project_id: String) -> (U128, U128) {
+        let type_stats = self
+            .donation_type_stats
+            .get(&DonationType::Project { project_id })
+            .unwrap_or_default();
+        (type_stats.donated, type_stats.token_airdropped)
+    }
+
+    pub fn get_stats(&self) -> Stats {
+        self.stats.clone()
+    }
+
+    pub fn get_donation_type_stats(&self, donation_type: DonationType) -> DonationTypeStats {
+        self.donation_type_stats.get(&donation_type).unwrap_or_default()
+    }
+
+    pub fn get_total_distributed(&self) -> U128 {
+        U128(self.total_distributed)
+    }
+
+    pub fn get_donor_count(&self) -> u64 {
+        self.donors.len()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{VMContextBuilder, accounts};
+    use near_sdk::testing_env;
+
+    fn create_test_campaign(contract: &mut DonorPayouts, campaign_id: &str) {
+        contract.create_campaign(
+            campaign_id.to_string(),
+            "Test campaign".to_string(),
+            accounts(4),
+            0,
+            1_000_000,
+            U128(1_000),
+            None,
+        );
+    }
+
+    fn register_test_project(contract: &mut DonorPayouts, project_id: &str) {
+        contract.register_project(project_id.to_string(), accounts(5), "Test project".to_string(), None, None);
+    }
+
+    #[test]
+    fn test_log_airdrop_multiple_donation_and_reward_types() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+
+       
+        contract.log_airdrop(
+            accounts(1),
+            "".to_string(),
+            DonationType::Campaign { campaign_id: "campaign1".to_string() },
+            U128(1),
+        );
+
+      
+        let mut context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .build();
+        context.attached_deposit = NearToken::from_yoctonear(2000);
+        testing_env!(context);
+
+       
+        contract.log_airdrop(
+            accounts(1),
+            "channel123".to_string(),
+            DonationType::Pot { pot_id: accounts(2) },
+            U128(2),
+        );
+
+        let donor = contract.get_donor(accounts(1)).unwrap();
+
+        assert_eq!(donor.wallet_id, accounts(1));
+        assert_eq!(donor.airdrop_amount, U128(3));
+        assert_eq!(donor.donation_amount, U128(3000));
+        assert!(!donor.paid);
+
+       
+        assert_eq!(donor.donation_types.len(), 2);
+        assert!(donor.donation_types.contains(&DonationType::Campaign { campaign_id: "campaign1".to_string() }));
+        assert!(donor.donation_types.contains(&DonationType::Pot { pot_id: accounts(2) }));
+
+       
+        assert_eq!(donor.reward_types.len(), 2);
+        assert!(donor.reward_types.contains(&RewardType::Token));
+        assert!(donor.reward_types.iter().any(|r| matches!(r, RewardType::NFT { channel_id, token_id } if channel_id == "channel123" && token_id.is_empty())));
+
+        assert_eq!(contract.get_total_distributed(), U128(3));
+
+        let records = contract.get_airdrop_records(0, 2).records;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].recipient, accounts(1));
+        assert_eq!(records[0].amount, U128(1));
+        assert_eq!(records[1].recipient, accounts(1));
+        assert_eq!(records[1].amount, U128(2));
+    }
+
+    #[test]
+    fn test_record_donation_multiple_types() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        register_test_project(&mut contract, "project1");
+
+       
+        contract.record_donation(DonationType::Direct, None, None, None);
+
+
+        let mut context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .build();
+        context.attached_deposit = NearToken::from_yoctonear(2000);
+        testing_env!(context);
+        contract.record_donation(DonationType::Project { project_id: "project1".to_string() }, None, None, None);
+
+        let donor = contract.get_donor(accounts(1)).unwrap();
+
+        assert_eq!(donor.donation_amount, U128(3000)); 
+        assert_eq!(donor.donation_types.len(), 2);
+        assert!(donor.donation_types.contains(&DonationType::Direct));
+        assert!(donor.donation_types.contains(&DonationType::Project { project_id: "project1".to_string() }));
+    }
+
+    #[test]
+    fn test_select_nft_reward_adds_types() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+
+      
+        contract.log_airdrop(
+            accounts(1),
+            "".to_string(),
+            DonationType::Campaign { campaign_id: "campaign1".to_string() },
+            U128(1),
+        );
+
+      
+        let mut context = VMContextBuilder::new()
+            .build();
+        context.predecessor_account_id = accounts(1);
+        testing_env!(context);
+        contract.select_nft_reward("channel123".to_string(), DonationType::Direct);
+
+        let donor = contract.get_donor(accounts(1)).unwrap();
+
+        assert_eq!(donor.donation_types.len(), 2);
+        assert!(donor.donation_types.contains(&DonationType::Campaign { campaign_id: "campaign1".to_string() }));
+        assert!(donor.donation_types.contains(&DonationType::Direct));
+
+        assert_eq!(donor.reward_types.len(), 2);
+        assert!(donor.reward_types.contains(&RewardType::Token));
+        assert!(donor.reward_types.iter().any(|r| matches!(r, RewardType::NFT { channel_id, token_id } if channel_id == "channel123" && token_id.is_empty())));
+    }
+
+    #[test]
+    fn test_get_donors_by_donation_type() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        register_test_project(&mut contract, "project1");
+
+        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Campaign { campaign_id: "campaign1".to_string() }, U128(1));
+        let mut context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .build();
+        context.attached_deposit = NearToken::from_yoctonear(2000);
+        testing_env!(context);
+        contract.log_airdrop(accounts(1), "channel123".to_string(), DonationType::Project { project_id: "project1".to_string() }, U128(1));
+        contract.log_airdrop(accounts(2), "".to_string(), DonationType::Project { project_id: "project1".to_string() }, U128(1));
+
+        let result = contract.get_donors_by_donation_type(DonationType::Campaign { campaign_id: "campaign1".to_string() }, 0, 1);
+        assert_eq!(result.donors.len(), 1);
+        assert_eq!(result.donors[0].wallet_id, accounts(1));
+        assert_eq!(result.donors[0].donation_amount, U128(3000)); 
+        assert!(!result.has_more);
+
+        let result = contract.get_donors_by_donation_type(DonationType::Project { project_id: "project1".to_string() }, 0, 2);
+        assert_eq!(result.donors.len(), 2);
+        assert_eq!(result.donors[0].wallet_id, accounts(1));
+        assert_eq!(result.donors[1].wallet_id, accounts(2));
+        assert!(!result.has_more);
+    }
+
+    #[test]
+    fn test_get_airdrop_records() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+
+        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Direct, U128(1));
+        contract.log_airdrop(accounts(2), "channel123".to_string(), DonationType::Pot { pot_id: accounts(3) }, U128(1));
+
+        let result = contract.get_airdrop_records(0, 1);
+        assert_eq!(result.records.len(), 1);
+        assert_eq!(result.records[0].recipient, accounts(1));
+        assert!(result.has_more);
+
+        let result = contract.get_airdrop_records(1, 1);
+        assert_eq!(result.records.len(), 1);
+        assert!(!result.has_more);
+    }
+
+    #[test]
+    fn test_get_project_rewards() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        register_test_project(&mut contract, "project1");
+
+        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Project { project_id: "project1".to_string() }, U128(1));
+        let mut context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .build();
+        context.attached_deposit = NearToken::from_yoctonear(2000);
+        testing_env!(context);
+        contract.log_airdrop(accounts(2), "".to_string(), DonationType::Project { project_id: "project1".to_string() }, U128(1));
+
+        let (total_donations, total_airdropped) = contract.get_project_rewards("project1".to_string());
+        assert_eq!(total_donations, U128(3000));
+        assert_eq!(total_airdropped, U128(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Campaign ID must be 64 characters or less")]
+    fn test_log_airdrop_invalid_campaign_id() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+
+        let long_campaign_id = "a".repeat(65);
+        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Campaign { campaign_id: long_campaign_id }, U128(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Donor not found")]
+    fn test_select_nft_reward_no_donor() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+
+        contract.select_nft_reward("channel123".to_string(), DonationType::Direct);
+    }
+
+    #[test]
+    fn test_archive_records_folds_paid_records() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+
+        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Direct, U128(5));
+        contract.log_airdrop(accounts(2), "".to_string(), DonationType::Direct, U128(7));
+        contract.log_airdrop(accounts(3), "channel123".to_string(), DonationType::Direct, U128(1));
+        contract.mark_payout_complete(accounts(1));
+        contract.mark_payout_complete(accounts(3));
+
+        let result = contract.archive_records(0, 10);
+        assert_eq!(result.archived, 2);
+        assert!(result.done);
+
+        let records = contract.get_airdrop_records(0, 10).records;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].recipient, accounts(2));
+
+        let summary = contract.get_archive_summary(DonationType::Direct).unwrap();
+        assert_eq!(summary.record_count, 2);
+        assert_eq!(summary.token_amount, U128(5));
+        assert_eq!(summary.nft_count, 1);
+
+        let status = contract.get_archive_status();
+        assert_eq!(status.archived_count, 2);
+        assert_eq!(status.live_records, 1);
+        assert_ne!(status.commitment, to_hex(&[0; 32]));
+        assert_eq!(contract.get_total_distributed(), U128(13));
+    }
+
+    #[test]
+    fn test_archive_records_full_batch_fits_log_limit() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        for i in 0..100u32 {
+            // A fresh context per record keeps the setup under the per-call log count.
+            testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+            let recipient: AccountId = format!("{:064x}", i).parse().unwrap();
+            contract.log_airdrop(recipient.clone(), "".to_string(), DonationType::Direct, U128(1));
+            contract.mark_payout_complete(recipient);
+        }
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let result = contract.archive_records(0, 100);
+        assert_eq!(result.archived, 100);
+        assert!(result.done);
+        assert_eq!(contract.get_archive_summary(DonationType::Direct).unwrap().token_amount, U128(100));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only admin can call this function")]
+    fn test_archive_records_admin_only() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(1)).build());
+        contract.archive_records(0, 10);
+    }
+
+    #[test]
+    fn test_get_stats_tracks_donations_and_payouts() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+
+        contract.record_donation(DonationType::Direct, None, None, None);
+        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Pot { pot_id: accounts(3) }, U128(5));
+        contract.log_airdrop(accounts(2), "channel123".to_string(), DonationType::Direct, U128(1));
+        contract.mark_payout_complete(accounts(1));
+
+        let stats = contract.get_stats();
+        assert_eq!(stats.total_donated, U128(3000));
+        assert_eq!(stats.donation_count, 3);
+        assert_eq!(stats.by_donation_type.direct.donated, U128(2000));
+        assert_eq!(stats.by_donation_type.direct.nft_airdropped, 1);
+        assert_eq!(stats.by_donation_type.pot.token_airdropped, U128(5));
+        assert_eq!(stats.token_rewards.record_count, 1);
+        assert_eq!(stats.token_rewards.paid_amount, U128(5));
+        assert_eq!(stats.token_rewards.unpaid_amount, U128(0));
+        assert_eq!(stats.nft_rewards.paid_count, 0);
+        assert_eq!(stats.nft_rewards.unpaid_count, 1);
+
+        let pot_stats = contract.get_donation_type_stats(DonationType::Pot { pot_id: accounts(3) });
+        assert_eq!(pot_stats.donated, U128(1000));
+        assert_eq!(pot_stats.donation_count, 1);
+    }
+
+    #[test]
+    fn test_donation_history_by_donor_and_type() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .block_timestamp(10)
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        register_test_project(&mut contract, "project1");
+
+        contract.record_donation(DonationType::Direct, Some("keep it up".to_string()), None, None);
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(2000))
+            .block_timestamp(20)
+            .build();
+        testing_env!(context);
+        contract.record_donation(DonationType::Project { project_id: "project1".to_string() }, None, None, None);
+        contract.record_donation(DonationType::Direct, None, None, None);
+
+        let history = contract.get_donations_by_donor(accounts(1), 0, 2);
+        assert_eq!(history.donations.len(), 2);
+        assert!(history.has_more);
+        assert_eq!(history.donations[0].amount, U128(1000));
+        assert_eq!(history.donations[0].timestamp, 10);
+        assert_eq!(history.donations[0].memo, Some("keep it up".to_string()));
+        assert_eq!(history.donations[1].donation_type, DonationType::Project { project_id: "project1".to_string() });
+
+        let direct = contract.get_donations_by_donation_type(DonationType::Direct, 0, 10);
+        assert_eq!(direct.donations.iter().map(|d| d.id).collect::<Vec<_>>(), vec![0, 2]);
+        assert!(!direct.has_more);
+
+        assert!(contract.get_donations_by_donor(accounts(2), 0, 10).donations.is_empty());
+        assert_eq!(contract.get_donation(2).unwrap().amount, U128(2000));
+    }
+
+    #[test]
+    fn test_campaign_lifecycle_status() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(0)
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.create_campaign("spring".to_string(), "Spring raise".to_string(), accounts(4), 100, 200, U128(50), None);
+
+        assert_eq!(contract.get_campaign("spring".to_string()).unwrap().status, CampaignStatus::Upcoming);
+
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .block_timestamp(150)
+            .build();
+        testing_env!(context);
+        contract.record_donation(DonationType::Campaign { campaign_id: "spring".to_string() }, None, None, None);
+        assert_eq!(contract.get_campaign("spring".to_string()).unwrap().status, CampaignStatus::Active);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(4)).block_timestamp(150).build());
+        contract.close_campaign("spring".to_string());
+
+        let campaigns = contract.get_campaigns(0, 10);
+        assert_eq!(campaigns.campaigns.len(), 1);
+        assert_eq!(campaigns.campaigns[0].status, CampaignStatus::Closed);
+        assert_eq!(campaigns.campaigns[0].campaign.reward_token, "token.testnet".parse::<AccountId>().unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "Campaign not found")]
+    fn test_record_donation_unknown_campaign() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+
+        contract.record_donation(DonationType::Campaign { campaign_id: "campain1".to_string() }, None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Campaign is not accepting donations")]
+    fn test_record_donation_ended_campaign() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .block_timestamp(2_000_000)
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+
+        contract.record_donation(DonationType::Campaign { campaign_id: "campaign1".to_string() }, None, None, None);
+    }
+
+    #[test]
+    fn test_project_registry_management() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        register_test_project(&mut contract, "project1");
+
+        let project = contract.update_project("project1".to_string(), Some(accounts(3)), None, Some("Clean water".to_string()), None);
+        assert_eq!(project.beneficiary, accounts(3));
+        assert_eq!(project.name, "Test project");
+        assert_eq!(project.description, Some("Clean water".to_string()));
+
+        contract.set_project_active("project1".to_string(), false);
+        let projects = contract.get_projects(0, 10);
+        assert_eq!(projects.projects.len(), 1);
+        assert!(!projects.projects[0].active);
+        assert!(!projects.has_more);
+    }
+
+    #[test]
+    #[should_panic(expected = "Project is not active")]
+    fn test_record_donation_inactive_project() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        register_test_project(&mut contract, "project1");
+        contract.set_project_active("project1".to_string(), false);
+
+        contract.record_donation(DonationType::Project { project_id: "project1".to_string() }, None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Project not found")]
+    fn test_log_airdrop_unknown_project() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+
+        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Project { project_id: "project2".to_string() }, U128(1));
+    }
+
+    fn callback_context(result: PromiseResult) {
+        callback_results(vec![result]);
+    }
+
+    fn callback_results(results: Vec<PromiseResult>) {
+        let context = VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0))
+            .build();
+        testing_env!(
+            context,
+            near_sdk::test_vm_config(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            results,
+        );
+    }
+
+    #[test]
+    fn test_forwarded_donation_resolves_beneficiary() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        register_test_project(&mut contract, "project1");
+        contract.set_treasury(accounts(3));
+
+        assert_eq!(contract.resolve_beneficiary(&DonationType::Direct), accounts(3));
+        assert_eq!(contract.resolve_beneficiary(&DonationType::Pot { pot_id: accounts(2) }), accounts(2));
+        assert_eq!(contract.resolve_beneficiary(&DonationType::Project { project_id: "project1".to_string() }), accounts(5));
+
+        contract.record_donation(DonationType::Project { project_id: "project1".to_string() }, None, None, None);
+        assert_eq!(contract.get_donation(0).unwrap().status, DonationStatus::Pending);
+
+        callback_context(PromiseResult::Successful(vec![]));
+        contract.on_donation_forwarded(0, accounts(5), vec![]);
+        assert_eq!(contract.get_donation(0).unwrap().status, DonationStatus::Forwarded);
+    }
+
+    #[test]
+    fn test_failed_forward_is_held_and_refundable() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.record_donation(DonationType::Direct, None, None, None);
+
+        callback_context(PromiseResult::Failed);
+        contract.on_donation_forwarded(0, accounts(1), vec![]);
+        assert_eq!(contract.get_donation(0).unwrap().status, DonationStatus::Held);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(1)).build());
+        contract.refund_held_donation(0);
+        assert_eq!(contract.get_donation(0).unwrap().status, DonationStatus::Refunded);
+        assert_eq!(contract.get_donor(accounts(1)).unwrap().donation_amount, U128(0));
+        assert_eq!(contract.get_stats().total_donated, U128(0));
+
+        // A failed refund transfer puts the donation back on hold.
+        callback_context(PromiseResult::Failed);
+        contract.on_donation_refunded(0, DonationStatus::Held);
+        assert_eq!(contract.get_donation(0).unwrap().status, DonationStatus::Held);
+        assert_eq!(contract.get_donor(accounts(1)).unwrap().donation_amount, U128(1000));
+        assert_eq!(contract.get_stats().total_donated, U128(1000));
+    }
+
+    #[test]
+    fn test_ft_on_transfer_records_token_donation() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        register_test_project(&mut contract, "project1");
+        contract.add_accepted_token(accounts(3));
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(3)).build());
+        let msg = json!({ "donation_type": { "Project": { "project_id": "project1" } } }).to_string();
+        contract.ft_on_transfer(accounts(1), U128(500), msg.clone());
+        contract.ft_on_transfer(accounts(1), U128(250), msg);
+
+        let donor = contract.get_donor(accounts(1)).unwrap();
+        assert_eq!(donor.donation_amount, U128(0));
+        assert_eq!(donor.ft_donations, vec![TokenAmount { token_id: accounts(3), amount: U128(750) }]);
+        assert!(donor.donation_types.contains(&DonationType::Project { project_id: "project1".to_string() }));
+
+        let donation = contract.get_donation(0).unwrap();
+        assert_eq!(donation.token_id, Some(accounts(3)));
+        assert_eq!(donation.status, DonationStatus::Pending);
+
+        let stats = contract.get_stats();
+        assert_eq!(stats.donation_count, 2);
+        assert_eq!(stats.total_donated, U128(0));
+        assert_eq!(stats.ft_donated, vec![TokenAmount { token_id: accounts(3), amount: U128(750) }]);
+        let project_stats = contract.get_donation_type_stats(DonationType::Project { project_id: "project1".to_string() });
+        assert_eq!(project_stats.ft_donated[0].amount, U128(750));
+    }
+
+    #[test]
+    #[should_panic(expected = "is not accepted for donations")]
+    fn test_ft_on_transfer_rejects_unknown_token() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(3))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+
+        contract.ft_on_transfer(accounts(1), U128(500), json!({ "donation_type": "Direct" }).to_string());
+    }
+
+    #[test]
+    fn test_record_donation_splits_protocol_and_referral_fees() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_fees(200, 100);
+
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(10_000))
+            .build();
+        testing_env!(context);
+        contract.record_donation(DonationType::Direct, None, Some(accounts(2)), None);
+        contract.record_donation(DonationType::Direct, None, None, None);
+
+        let donation = contract.get_donation(0).unwrap();
+        assert_eq!(donation.amount, U128(10_000));
+        assert_eq!(donation.protocol_fee, U128(200));
+        assert_eq!(donation.referral_fee, U128(100));
+        assert_eq!(donation.net_amount, U128(9_700));
+        assert_eq!(contract.get_donation(1).unwrap().net_amount, U128(9_800));
+
+        let donor_fees = contract.get_fee_account(accounts(1));
+        assert_eq!(donor_fees.protocol_fees_paid, U128(400));
+        assert_eq!(donor_fees.referral_fees_paid, U128(100));
+        let referrer_fees = contract.get_fee_account(accounts(2));
+        assert_eq!(referrer_fees.referral_fees_earned, U128(100));
+        assert_eq!(referrer_fees.referral_count, 1);
+
+        let stats = contract.get_stats();
+        assert_eq!(stats.total_donated, U128(20_000));
+        assert_eq!(stats.protocol_fees, U128(400));
+        assert_eq!(stats.referral_fees, U128(100));
+        assert!(near_sdk::test_utils::get_logs()
+            .iter()
+            .any(|l| l.starts_with("EVENT_JSON:") && l.contains("\"net_amount\":\"9800\"")));
+    }
+
+    #[test]
+    #[should_panic(expected = "Protocol fee cannot exceed 1000 basis points")]
+    fn test_set_fees_rejects_excessive_fee() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+
+        contract.set_fees(1_001, 0);
+    }
+
+    #[test]
+    fn test_refund_donation_within_window() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(100);
+        contract.set_fees(200, 0);
+
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .block_timestamp(10)
+            .build();
+        testing_env!(context);
+        let donation_id = contract.record_donation(DonationType::Direct, None, None, None);
+        contract.record_donation(DonationType::Direct, None, None, None);
+
+        let donation = contract.get_donation(donation_id).unwrap();
+        assert_eq!(donation.status, DonationStatus::Escrowed);
+        assert_eq!(donation.refundable_until, Some(110));
+
+        contract.refund_donation(donation_id);
+        assert_eq!(contract.get_donation(donation_id).unwrap().status, DonationStatus::Refunded);
+        assert_eq!(contract.get_donor(accounts(1)).unwrap().donation_amount, U128(1000));
+        assert_eq!(contract.get_stats().total_donated, U128(1000));
+        assert_eq!(contract.get_fee_account(accounts(1)).protocol_fees_paid, U128(20));
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(2)).block_timestamp(110).build());
+        contract.release_donation(1);
+        assert_eq!(contract.get_donation(1).unwrap().status, DonationStatus::Pending);
+    }
+
+    #[test]
+    #[should_panic(expected = "Refund window has ended")]
+    fn test_refund_donation_after_window() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .block_timestamp(10)
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(100);
+        let donation_id = contract.record_donation(DonationType::Direct, None, None, None);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(1)).block_timestamp(110).build());
+        contract.refund_donation(donation_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Refund window has not ended yet")]
+    fn test_release_donation_before_window() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .block_timestamp(10)
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(100);
+        let donation_id = contract.record_donation(DonationType::Direct, None, None, None);
+
+        contract.release_donation(donation_id);
+    }
+
+    #[test]
+    fn test_campaign_caps_partially_refund_excess() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        contract.set_campaign_limits(
+            "campaign1".to_string(),
+            DonationLimits {
+                min_amount: Some(U128(100)),
+                max_amount: None,
+                per_donor_cap: Some(U128(1_500)),
+                hard_cap: Some(U128(2_000)),
+            },
+        );
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1_000))
+            .build();
+        testing_env!(context.clone());
+        contract.record_donation(campaign.clone(), None, None, None);
+        contract.record_donation(campaign.clone(), None, None, None);
+        assert_eq!(contract.get_donation(1).unwrap().amount, U128(500));
+        assert_eq!(contract.get_campaign_donor_total("campaign1".to_string(), accounts(1)), U128(1_500));
+
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(NearToken::from_yoctonear(1_000))
+            .build();
+        testing_env!(context);
+        contract.record_donation(campaign, None, None, None);
+        assert_eq!(contract.get_donation(2).unwrap().amount, U128(500));
+        assert_eq!(contract.get_campaign("campaign1".to_string()).unwrap().campaign.raised, U128(2_000));
+        assert_eq!(contract.get_donor(accounts(2)).unwrap().donation_amount, U128(500));
+    }
+
+    #[test]
+    #[should_panic(expected = "Campaign cap reached")]
+    fn test_campaign_hard_cap_rejects_when_full() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1_000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        contract.set_campaign_limits(
+            "campaign1".to_string(),
+            DonationLimits { hard_cap: Some(U128(1_000)), ..Default::default() },
+        );
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+
+        contract.record_donation(campaign.clone(), None, None, None);
+        contract.record_donation(campaign, None, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Donation is below the minimum of 500 yoctoNEAR")]
+    fn test_global_minimum_donation() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(100))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_donation_limits(U128(500), None);
+
+        contract.record_donation(DonationType::Direct, None, None, None);
+    }
+
+    #[test]
+    fn test_moderator_hides_memo_without_deleting_donation() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.add_moderator(accounts(3));
+        contract.record_donation(DonationType::Direct, Some("  go team  ".to_string()), None, None);
+        assert_eq!(contract.get_donation(0).unwrap().memo, Some("go team".to_string()));
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(3)).build());
+        contract.hide_memo(0, Some("spam".to_string()));
+
+        let donation = contract.get_donation(0).unwrap();
+        assert!(donation.memo_hidden);
+        assert_eq!(donation.memo, None);
+        assert_eq!(donation.amount, U128(1000));
+        assert_eq!(contract.get_donations(0, 10).donations[0].memo, None);
+
+        contract.unhide_memo(0);
+        assert_eq!(contract.get_donation(0).unwrap().memo, Some("go team".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Memo must be 280 bytes or less")]
+    fn test_record_donation_memo_too_long() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+
+        contract.record_donation(DonationType::Direct, Some("é".repeat(141)), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only admin or moderators can call this function")]
+    fn test_hide_memo_requires_moderator() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.record_donation(DonationType::Direct, Some("hello".to_string()), None, None);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(1)).build());
+        contract.hide_memo(0, None);
+    }
+
+    #[test]
+    fn test_anonymous_donation_hidden_from_public_views() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+
+        let donation_id = contract.record_donation(DonationType::Direct, None, None, Some(true));
+        let anonymous: AccountId = ANONYMOUS_ACCOUNT_ID.parse().unwrap();
+        assert_eq!(contract.get_donors(0, 10).donors[0].wallet_id, anonymous);
+        assert_eq!(contract.get_donors_by_donation_type(DonationType::Direct, 0, 10).donors[0].wallet_id, anonymous);
+
+        // A later public donation is listed under the donor, without the anonymous one.
+        let public_id = contract.record_donation(DonationType::Direct, None, None, None);
+        assert_eq!(contract.get_donation(donation_id).unwrap().donor, anonymous);
+        assert_eq!(contract.get_donations(0, 10).donations[1].donor, accounts(1));
+        let listed = &contract.get_donors(0, 10).donors[0];
+        assert_eq!(listed.wallet_id, accounts(1));
+        assert_eq!(listed.donation_amount.0, 1000);
+        assert_eq!(listed.anonymous_amount.0, 0);
+        let history = contract.get_donations_by_donor(accounts(1), 0, 10).donations;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].id, public_id);
+
+        let donor = contract.get_donor(accounts(1)).unwrap();
+        assert_eq!(donor.anonymous_amount.0, 1000);
+        assert_eq!(donor.wallet_id, accounts(1));
+        assert_eq!(donor.donation_amount.0, 2000);
+    }
+
+    #[test]
+    fn test_anonymous_donation_type_hidden_from_public_donor() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        register_test_project(&mut contract, "project1");
+        let project = DonationType::Project { project_id: "project1".to_string() };
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build());
+        contract.record_donation(DonationType::Direct, None, None, None);
+        contract.record_donation(project.clone(), None, None, Some(true));
+
+        assert!(contract.get_donors_by_donation_type(project.clone(), 0, 10).donors.is_empty());
+        let listed = contract.get_donors_by_donation_type(DonationType::Direct, 0, 10).donors;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].wallet_id, accounts(1));
+        assert_eq!(listed[0].donation_types, vec![DonationType::Direct]);
+        assert_eq!(contract.get_donors(0, 10).donors[0].donation_types, vec![DonationType::Direct]);
+        assert!(contract.get_donor(accounts(1)).unwrap().donation_types.contains(&project));
+    }
+
+    #[test]
+    fn test_refund_event_redacts_anonymous_donor() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(1_000);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(1000)).build());
+        let donation_id = contract.record_donation(DonationType::Direct, None, None, Some(true));
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.refund_donation(donation_id);
+
+        callback_context(PromiseResult::Successful(vec![]));
+        contract.on_donation_refunded(donation_id, DonationStatus::Escrowed);
+        let logs = near_sdk::test_utils::get_logs().join("\n");
+        assert!(logs.contains("donation_refunded") && logs.contains(ANONYMOUS_ACCOUNT_ID));
+        assert!(!logs.contains(accounts(1).as_str()));
+    }
+
+    #[test]
+    fn test_record_donation_for_credits_beneficiary_and_refunds_payer() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(1_000);
+
+        let donation_id = contract.record_donation_for(accounts(2), DonationType::Direct, None, None, None);
+
+        let donation = contract.get_donation(donation_id).unwrap();
+        assert_eq!(donation.donor, accounts(2));
+        assert_eq!(donation.payer, accounts(1));
+        assert_eq!(contract.get_donor(accounts(2)).unwrap().donation_amount.0, 1000);
+        assert!(contract.get_donor(accounts(1)).is_none());
+
+        contract.refund_donation(donation_id);
+        assert_eq!(contract.get_donation(donation_id).unwrap().status, DonationStatus::Refunded);
+    }
+
+    #[test]
+    fn test_gifts_are_listed_by_payer() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+
+        contract.record_donation(DonationType::Direct, None, None, None);
+        let gift_id = contract.record_donation_for(accounts(2), DonationType::Direct, None, None, None);
+
+        let gifts = contract.get_gifts_by_payer(accounts(1), 0, 10);
+        assert_eq!(gifts.donations.len(), 1);
+        assert_eq!(gifts.donations[0].id, gift_id);
+        assert_eq!(gifts.donations[0].donor, accounts(2));
+        assert!(contract.get_gifts_by_payer(accounts(2), 0, 10).donations.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the donor can refund a donation")]
+    fn test_gift_beneficiary_cannot_refund() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(1_000);
+        let donation_id = contract.record_donation_for(accounts(2), DonationType::Direct, None, None, None);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(2)).build());
+        contract.refund_donation(donation_id);
+    }
+
+    #[test]
+    fn test_process_recurring_donations_from_prepaid_balance() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(2500));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+
+        let recurring = contract.create_recurring_donation(U128(1000), 100, DonationType::Direct);
+        assert_eq!(contract.get_prepaid_balance(accounts(1)).0, 2500);
+
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        assert_eq!(contract.process_recurring(10), 1);
+        // Not due again until the interval has passed.
+        assert_eq!(contract.process_recurring(10), 0);
+
+        testing_env!(context.block_timestamp(100).build());
+        assert_eq!(contract.process_recurring(10), 1);
+        assert_eq!(contract.get_prepaid_balance(accounts(1)).0, 500);
+        assert_eq!(contract.get_donor(accounts(1)).unwrap().donation_amount.0, 2000);
+        assert_eq!(contract.get_donations_by_donor(accounts(1), 0, 10).donations.len(), 2);
+
+        // The balance no longer covers a payment, so the period is skipped.
+        testing_env!(context.block_timestamp(200).build());
+        assert_eq!(contract.process_recurring(10), 0);
+        let recurring = contract.get_recurring_donation(recurring.id).unwrap();
+        assert_eq!(recurring.executed_count, 2);
+        assert_eq!(recurring.skipped_count, 1);
+        assert_eq!(recurring.next_due, 300);
+    }
+
+    #[test]
+    fn test_process_recurring_stops_before_running_out_of_gas() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(10_000));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.create_matching_pool(DonationType::Direct, 10_000, None);
+        for donor in 1..5 {
+            testing_env!(context.predecessor_account_id(accounts(donor)).attached_deposit(NearToken::from_yoctonear(1000)).build());
+            contract.create_recurring_donation(U128(1000), 100, DonationType::Direct);
+        }
 
-        log!("Initiating token reward process for {}", signer);
+        // Every forward reserves gas for paying its match, so four do not fit in one call.
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        let executed = contract.process_recurring(4);
+        assert!(executed > 0 && executed < 4);
 
-        Promise::new(self.token_contract.clone())
-            .function_call(
-                "storage_balance_of".to_string(),
-                json!({ "account_id": signer })
-                    .to_string()
-                    .into_bytes(),
-                NearToken::from_yoctonear(0),
-                Gas::from_tgas(20),
-            )
-            .then(
-                Self::ext(env::current_account_id())
-                    .with_static_gas(Gas::from_tgas(80))
-                    .on_storage_check_callback(
-                        signer.clone(),
-                        donor.airdrop_amount,
-                        env::attached_deposit(),
-                    ),
-            )
+        testing_env!(context.build());
+        assert_eq!(executed + contract.process_recurring(4), 4);
+        for donor in 1..5 {
+            assert_eq!(contract.get_prepaid_balance(accounts(donor)).0, 0);
+        }
     }
 
-    #[private]
-    pub fn on_storage_check_callback(
-        &mut self,
-        signer: AccountId,
-        amount: U128,
-        attached_deposit: NearToken,
-    ) -> Promise {
-        assert_eq!(
-            env::promise_results_count(),
-            1,
-            "Expected one promise result"
-        );
+    #[test]
+    fn test_cancel_recurring_donation_and_withdraw_balance() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(2500));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        let recurring = contract.create_recurring_donation(U128(1000), 100, DonationType::Direct);
 
-        match env::promise_result(0) {
-            PromiseResult::Successful(result) => {
-                let balance: Value = serde_json::from_slice(&result)
-                    .expect("Failed to parse storage_balance_of result");
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.cancel_recurring_donation(recurring.id);
+        assert_eq!(contract.process_recurring(10), 0);
+        assert!(contract.get_recurring_donation(recurring.id).is_none());
 
-                if balance != Value::Null {
-                    log!("Account {} is registered, proceeding with transfer", signer);
-                    self.perform_ft_transfer(signer, amount)
-                } else {
-                    log!("Account {} is not registered, registering now", signer);
-                    let storage_deposit_amount = NearToken::from_millinear(1250);
-                    assert!(
-                        attached_deposit >= storage_deposit_amount,
-                        "Insufficient deposit for storage registration, need at least 0.00125 NEAR"
-                    );
+        contract.withdraw_prepaid(None);
+        assert_eq!(contract.get_prepaid_balance(accounts(1)).0, 0);
 
-                    Promise::new(self.token_contract.clone())
-                        .function_call(
-                            "storage_deposit".to_string(),
-                            json!({ "account_id": signer, "registration_only": true })
-                                .to_string()
-                                .into_bytes(),
-                            storage_deposit_amount,
-                            Gas::from_tgas(30),
-                        )
-                        .then(
-                            Self::ext(env::current_account_id())
-                                .with_static_gas(Gas::from_tgas(60))
-                                .on_storage_deposit_callback(signer, amount),
-                        )
-                }
-            }
-            PromiseResult::Failed => {
-                log!("Failed to check storage balance for {}", signer);
-                panic!("Storage balance check failed");
-            }
+        callback_context(PromiseResult::Failed);
+        contract.on_prepaid_withdrawn(accounts(1), U128(2500));
+        assert_eq!(contract.get_prepaid_balance(accounts(1)).0, 2500);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the donor can cancel a recurring donation")]
+    fn test_cancel_recurring_donation_requires_donor() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        let recurring = contract.create_recurring_donation(U128(1000), 100, DonationType::Direct);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(2)).build());
+        contract.cancel_recurring_donation(recurring.id);
+    }
+
+    #[test]
+    fn test_matching_pool_matches_forwarded_donations() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(2)).attached_deposit(NearToken::from_yoctonear(1000));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        let pool = contract.create_matching_pool(DonationType::Direct, 10_000, Some(U128(600)));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let donation_id = contract.record_donation(DonationType::Direct, None, None, None);
+        assert_eq!(contract.get_donation(donation_id).unwrap().matched_amount.0, 600);
+        assert_eq!(contract.get_matching_pool(pool.id).unwrap().balance.0, 400);
+
+        callback_context(PromiseResult::Successful(vec![]));
+        contract.on_donation_forwarded(donation_id, accounts(0), vec![]);
+        assert_eq!(contract.get_matching_pool(pool.id).unwrap().matched.0, 0);
+        contract.on_matches_paid(donation_id, accounts(0));
+        let pool_view = contract.get_matching_pool(pool.id).unwrap();
+        assert_eq!(pool_view.matched.0, 600);
+        let history = contract.get_matching_history(pool.id, 0, 10).records;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].donation_id, donation_id);
+        assert_eq!(contract.get_donor(accounts(1)).unwrap().matched_amount.0, 600);
+
+        // The donor has used up their cap in this pool.
+        testing_env!(context.build());
+        let second_id = contract.record_donation(DonationType::Direct, None, None, None);
+        assert_eq!(contract.get_donation(second_id).unwrap().matched_amount.0, 0);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.close_matching_pool(pool.id);
+        let pool_view = contract.get_matching_pool(pool.id).unwrap();
+        assert!(pool_view.closed);
+        assert_eq!(pool_view.balance.0, 0);
+    }
+
+    #[test]
+    fn test_held_donation_returns_match_to_pool() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(2)).attached_deposit(NearToken::from_yoctonear(1000));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        let pool = contract.create_matching_pool(DonationType::Direct, 5_000, None);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let donation_id = contract.record_donation(DonationType::Direct, None, None, None);
+        assert_eq!(contract.get_matching_pool(pool.id).unwrap().balance.0, 500);
+
+        callback_context(PromiseResult::Failed);
+        contract.on_donation_forwarded(donation_id, accounts(0), vec![]);
+        let donation = contract.get_donation(donation_id).unwrap();
+        assert_eq!(donation.status, DonationStatus::Held);
+        assert_eq!(donation.matched_amount.0, 0);
+        assert_eq!(contract.get_matching_pool(pool.id).unwrap().balance.0, 1000);
+        assert_eq!(contract.get_donor(accounts(1)).unwrap().matched_amount.0, 0);
+        assert!(contract.get_matching_history(pool.id, 0, 10).records.is_empty());
+    }
+
+    #[test]
+    fn test_failed_match_payment_returns_to_pool() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(2)).attached_deposit(NearToken::from_yoctonear(1000));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        let pool = contract.create_matching_pool(DonationType::Direct, 10_000, None);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let donation_id = contract.record_donation(DonationType::Direct, None, None, None);
+        assert_eq!(contract.get_matching_pool(pool.id).unwrap().balance.0, 0);
+
+        callback_context(PromiseResult::Successful(vec![]));
+        contract.on_donation_forwarded(donation_id, accounts(0), vec![]);
+        callback_context(PromiseResult::Failed);
+        contract.on_matches_paid(donation_id, accounts(0));
+
+        let pool_view = contract.get_matching_pool(pool.id).unwrap();
+        assert_eq!(pool_view.balance.0, 1000);
+        assert_eq!(pool_view.matched.0, 0);
+        assert_eq!(contract.get_donation(donation_id).unwrap().matched_amount.0, 0);
+        assert_eq!(contract.get_donor(accounts(1)).unwrap().matched_amount.0, 0);
+
+        // The refilled pool is open again for the next donation.
+        testing_env!(context.build());
+        let second_id = contract.record_donation(DonationType::Direct, None, None, None);
+        assert_eq!(contract.get_donation(second_id).unwrap().matched_amount.0, 1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "This donation type already has 10 open matching pools")]
+    fn test_open_matching_pools_are_capped() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        for _ in 0..=MAX_OPEN_MATCHING_POOLS {
+            contract.create_matching_pool(DonationType::Direct, 10_000, None);
         }
     }
 
-    #[private]
-    pub fn on_storage_deposit_callback(&mut self, signer: AccountId, amount: U128) -> Promise {
-        assert_eq!(
-            env::promise_results_count(),
-            1,
-            "Expected one promise result"
-        );
+    #[test]
+    #[should_panic(expected = "Only the sponsor or admin can close a matching pool")]
+    fn test_close_matching_pool_requires_sponsor() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(1000));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
 
-        match env::promise_result(0) {
-            PromiseResult::Successful(_) => {
-                log!("Successfully registered {} with token contract", signer);
-                self.perform_ft_transfer(signer, amount)
-            }
-            PromiseResult::Failed => {
-                log!("Failed to register {} with token contract", signer);
-                panic!("Storage deposit failed");
-            }
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        let pool = contract.create_matching_pool(DonationType::Direct, 10_000, None);
+
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.close_matching_pool(pool.id);
+    }
+
+    #[test]
+    fn test_compute_airdrops_from_settled_donations() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(1000));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(100);
+        contract.set_reward_policy(DonationType::Direct, RewardPolicy::Ratio { ratio_bps: 5_000 });
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let first_id = contract.record_donation(DonationType::Direct, None, None, None);
+        testing_env!(context.block_timestamp(50).attached_deposit(NearToken::from_yoctonear(3000)).build());
+        contract.record_donation(DonationType::Direct, None, None, None);
+
+        // The second donation is still refundable, so only the first one is rewarded.
+        testing_env!(context.predecessor_account_id(accounts(0)).block_timestamp(120).build());
+        let result = contract.compute_airdrops(DonationType::Direct, 10);
+        assert_eq!(result.airdrops_created, 1);
+        assert_eq!(result.total_amount.0, 500);
+        assert_eq!(result.remaining, 1);
+        let record = &contract.get_airdrop_records(0, 10).records[0];
+        assert_eq!(record.recipient, accounts(1));
+        assert_eq!(record.donation_id, Some(first_id));
+        assert!(contract.get_donation(first_id).unwrap().rewarded);
+
+        testing_env!(context.block_timestamp(200).build());
+        let result = contract.compute_airdrops(DonationType::Direct, 10);
+        assert_eq!(result.airdrops_created, 1);
+        assert_eq!(result.total_amount.0, 1500);
+        assert_eq!(result.remaining, 0);
+        assert_eq!(contract.compute_airdrops(DonationType::Direct, 10).airdrops_created, 0);
+        assert_eq!(contract.get_donor(accounts(1)).unwrap().airdrop_amount.0, 2000);
+        assert_eq!(contract.get_total_distributed().0, 2000);
+    }
+
+    #[test]
+    fn test_reward_policy_amounts() {
+        let tiered = RewardPolicy::Tiered {
+            tiers: vec![
+                RewardTier { min_amount: U128(100), reward: U128(10) },
+                RewardTier { min_amount: U128(1000), reward: U128(150) },
+            ],
+        };
+        assert_eq!(tiered.reward_for(99), 0);
+        assert_eq!(tiered.reward_for(999), 10);
+        assert_eq!(tiered.reward_for(5000), 150);
+        assert_eq!(RewardPolicy::Flat { amount: U128(7) }.reward_for(1), 7);
+        assert_eq!(RewardPolicy::Ratio { ratio_bps: 20_000 }.reward_for(50), 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "Tiers must be sorted by ascending min_amount")]
+    fn test_set_reward_policy_rejects_unsorted_tiers() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        let tiers = vec![
+            RewardTier { min_amount: U128(1000), reward: U128(150) },
+            RewardTier { min_amount: U128(100), reward: U128(10) },
+        ];
+        contract.set_reward_policy(DonationType::Direct, RewardPolicy::Tiered { tiers });
+    }
+
+    #[test]
+    fn test_quadratic_distribution_over_several_calls() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        contract.set_refund_window(1_000);
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+
+        // accounts(1) gives 100 in total over two donations.
+        for (donor, amount) in [(1, 36), (2, 400), (3, 900), (1, 64)] {
+            testing_env!(context
+                .predecessor_account_id(accounts(donor))
+                .attached_deposit(NearToken::from_yoctonear(amount))
+                .build());
+            contract.record_donation(campaign.clone(), None, None, None);
+        }
+
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.start_quadratic_distribution(campaign.clone(), U128(1000));
+        let mut distribution = contract.process_distribution(campaign.clone(), 2);
+        assert_eq!(distribution.status, DistributionStatus::Collecting);
+        while distribution.status != DistributionStatus::Complete {
+            distribution = contract.process_distribution(campaign.clone(), 2);
         }
+
+        assert_eq!(distribution.donor_count, 3);
+        assert_eq!(distribution.total_weight.0, 60);
+        assert_eq!(distribution.distributed.0, 999);
+        assert_eq!(distribution.dust.0, 1);
+        let amounts: Vec<u128> = contract.get_airdrop_records(0, 10).records.iter().map(|r| r.amount.0).collect();
+        assert_eq!(amounts, vec![166, 333, 500]);
+        assert_eq!(contract.get_donor(accounts(3)).unwrap().airdrop_amount.0, 500);
     }
 
+    #[test]
+    #[should_panic(expected = "Rewards for this donation type have already been distributed")]
+    fn test_distribution_closes_refunds() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(1_000);
+        let donation_id = contract.record_donation(DonationType::Direct, None, None, None);
 
-    fn perform_ft_transfer(&self, receiver_id: AccountId, amount: U128) -> Promise {
-        log!(
-            "Initiating token transfer of {} for {}",
-            amount.0,
-            receiver_id
+        contract.start_quadratic_distribution(DonationType::Direct, U128(1000));
+        contract.refund_donation(donation_id);
+    }
+
+    #[test]
+    fn test_isqrt_and_mul_div() {
+        assert_eq!(isqrt(0), 0);
+        assert_eq!(isqrt(15), 3);
+        assert_eq!(isqrt(16), 4);
+        assert_eq!(isqrt(u128::MAX), u64::MAX as u128);
+        assert_eq!(mul_div(10, 3, 4), 7);
+        assert_eq!(mul_div(u128::MAX, 3, 4), (3u128 << 126) - 1);
+        assert_eq!(mul_div(u128::MAX, u128::MAX - 1, u128::MAX), u128::MAX - 1);
+    }
+
+    #[test]
+    fn test_finalize_round_allocates_pro_rata() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        register_test_project(&mut contract, "project1");
+        contract.set_refund_window(1_000);
+        let project = DonationType::Project { project_id: "project1".to_string() };
+
+        for (donor, amount) in [(1, 100), (2, 300), (3, 200)] {
+            testing_env!(context
+                .predecessor_account_id(accounts(donor))
+                .attached_deposit(NearToken::from_yoctonear(amount))
+                .build());
+            contract.record_donation(project.clone(), None, None, None);
+        }
+
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        let round = contract.finalize_round(project.clone(), U128(1000));
+        assert_eq!(round.status, DistributionStatus::Complete);
+        assert_eq!(round.mode, DistributionMode::ProRata);
+        assert_eq!(round.distributed.0, 999);
+        assert_eq!(round.dust.0, 1);
+        let amounts: Vec<u128> = contract.get_airdrop_records(0, 10).records.iter().map(|r| r.amount.0).collect();
+        assert_eq!(amounts, vec![166, 500, 333]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Round has already been finalized")]
+    fn test_finalize_round_twice() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(1_000);
+        contract.record_donation(DonationType::Direct, None, None, None);
+
+        contract.finalize_round(DonationType::Direct, U128(1000));
+        contract.finalize_round(DonationType::Direct, U128(1000));
+    }
+
+    #[test]
+    #[should_panic(expected = "Round is already being finalized with different parameters")]
+    fn test_finalize_round_rejects_changed_budget() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(1_000);
+        for _ in 0..FINALIZE_ROUND_BATCH + 1 {
+            testing_env!(context.attached_deposit(NearToken::from_yoctonear(10)).build());
+            contract.record_donation(DonationType::Direct, None, None, None);
+        }
+
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(0)).build());
+        let round = contract.finalize_round(DonationType::Direct, U128(1000));
+        assert_eq!(round.status, DistributionStatus::Collecting);
+        contract.finalize_round(DonationType::Direct, U128(2000));
+    }
+
+    fn set_test_human_registry(contract: &mut DonorPayouts, mode: HumanCheckMode) {
+        contract.set_human_registry(Some(HumanRegistry {
+            contract_id: "registry.testnet".parse().unwrap(),
+            method_name: "is_human".to_string(),
+            cache_ttl_ns: 1_000,
+            mode,
+        }));
+    }
+
+    /// Feeds `on_human_check` the answer a registry's `is_human` view would return.
+    fn mock_registry_answer(contract: &mut DonorPayouts, account_id: AccountId, is_human: bool, now: u64) {
+        let context = VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(now)
+            .build();
+        testing_env!(
+            context,
+            near_sdk::test_vm_config(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(serde_json::to_vec(&is_human).unwrap())],
         );
+        assert_eq!(contract.on_human_check(account_id), is_human);
+    }
 
-        Promise::new(self.token_contract.clone())
-            .function_call(
-                "ft_transfer".to_string(),
-                json!({
-                    "receiver_id": receiver_id,
-                    "amount": amount,
-                })
-                .to_string()
-                .into_bytes(),
-                NearToken::from_yoctonear(1),
-                Gas::from_tgas(50),
-            )
-            .then(
-                Self::ext(env::current_account_id())
-                    .with_static_gas(Gas::from_tgas(10))
-                    .on_token_transfer_callback(receiver_id, amount),
-            )
+    #[test]
+    fn test_human_registry_gates_log_airdrop() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        set_test_human_registry(&mut contract, HumanCheckMode::Reject);
+        contract.verify_human(accounts(1));
+
+        mock_registry_answer(&mut contract, accounts(1), true, 0);
+        testing_env!(context.block_timestamp(500).build());
+        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Direct, U128(100));
+        assert_eq!(contract.get_donor(accounts(1)).unwrap().airdrop_amount.0, 100);
+        assert!(contract.get_human_check(accounts(1)).unwrap().is_human);
+
+        // Once the cached answer expires the recipient has to verify again.
+        testing_env!(context.block_timestamp(1_000).build());
+        assert!(!contract.is_verified_human(&accounts(1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is not a verified human, call verify_human first")]
+    fn test_human_registry_rejects_non_human() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        set_test_human_registry(&mut contract, HumanCheckMode::Reject);
+        mock_registry_answer(&mut contract, accounts(1), false, 0);
+
+        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Direct, U128(100));
+    }
+
+    #[test]
+    fn test_human_registry_flag_mode_marks_donor() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        set_test_human_registry(&mut contract, HumanCheckMode::Flag);
+
+        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Direct, U128(100));
+        let donor = contract.get_donor(accounts(1)).unwrap();
+        assert!(donor.unverified);
+        assert_eq!(donor.airdrop_amount.0, 100);
+
+        mock_registry_answer(&mut contract, accounts(1), true, 0);
+        assert!(!contract.get_donor(accounts(1)).unwrap().unverified);
+    }
+
+    #[test]
+    fn test_human_registry_gates_computed_and_distributed_rewards() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(5);
+        contract.set_reward_policy(DonationType::Direct, RewardPolicy::Ratio { ratio_bps: 5_000 });
+        for donor in [accounts(1), accounts(2)] {
+            testing_env!(context.predecessor_account_id(donor).attached_deposit(NearToken::from_yoctonear(1000)).build());
+            contract.record_donation(DonationType::Direct, None, None, None);
+        }
+
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        set_test_human_registry(&mut contract, HumanCheckMode::Reject);
+        mock_registry_answer(&mut contract, accounts(1), true, 0);
+        mock_registry_answer(&mut contract, accounts(2), false, 0);
+        testing_env!(context.block_timestamp(10).build());
+
+        let round = contract.finalize_round(DonationType::Direct, U128(100));
+        assert_eq!(round.donor_count, 1);
+        assert_eq!(round.distributed.0, 100);
+        // The round already rewarded the first donor's donation.
+        let result = contract.compute_airdrops(DonationType::Direct, 10);
+        assert_eq!(result.airdrops_created, 0);
+        assert_eq!(result.skipped, 1);
+        assert!(contract.get_airdrop_records(0, 10).records.iter().all(|r| r.recipient == accounts(1)));
+    }
+
+    #[test]
+    fn test_compute_airdrops_waits_for_pending_human_check() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(5);
+        contract.set_reward_policy(DonationType::Direct, RewardPolicy::Ratio { ratio_bps: 5_000 });
+        set_test_human_registry(&mut contract, HumanCheckMode::Reject);
+        for donor in [accounts(1), accounts(2)] {
+            testing_env!(context.predecessor_account_id(donor).attached_deposit(NearToken::from_yoctonear(1000)).build());
+            contract.record_donation(DonationType::Direct, None, None, None);
+        }
+
+        // The registry has not answered for the first donor yet, so nothing is passed over.
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).block_timestamp(10).build());
+        let result = contract.compute_airdrops(DonationType::Direct, 10);
+        assert_eq!((result.airdrops_created, result.skipped, result.next_index), (0, 0, 0));
+        assert!(result.blocked_by.unwrap().contains("No current human check"));
+
+        mock_registry_answer(&mut contract, accounts(1), true, 10);
+        mock_registry_answer(&mut contract, accounts(2), true, 10);
+        testing_env!(context.block_timestamp(20).build());
+        let result = contract.compute_airdrops(DonationType::Direct, 10);
+        assert_eq!((result.airdrops_created, result.skipped, result.next_index), (2, 0, 2));
+        assert_eq!(result.blocked_by, None);
+    }
+
+    #[test]
+    fn test_compute_airdrops_waits_for_campaign_budget() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+        contract.set_reward_policy(campaign.clone(), RewardPolicy::Ratio { ratio_bps: 10_000 });
+        contract.log_airdrop(accounts(3), "".to_string(), campaign.clone(), U128(1_000));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(500)).build());
+        let donation_id = contract.record_donation(campaign.clone(), None, None, None);
+        callback_context(PromiseResult::Successful(vec![]));
+        contract.on_donation_forwarded(donation_id, accounts(4), vec![]);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        let result = contract.compute_airdrops(campaign.clone(), 10);
+        assert_eq!((result.airdrops_created, result.next_index), (0, 0));
+        assert_eq!(result.blocked_by.as_deref(), Some("Donation #0: Campaign token budget is spent"));
+
+        contract.set_campaign_budget("campaign1".to_string(), U128(2_000), None, RewardCaps::default());
+        let result = contract.compute_airdrops(campaign, 10);
+        assert_eq!((result.airdrops_created, result.total_amount.0, result.next_index), (1, 500, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is not a verified human, call verify_human first")]
+    fn test_human_registry_gates_claims() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Direct, U128(100));
+        set_test_human_registry(&mut contract, HumanCheckMode::Reject);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(1)).build());
+        contract.send_token_reward();
     }
 
-    #[private]
-    pub fn on_nft_mint_callback(&mut self, donor_id: AccountId) {
-        if env::promise_results_count() != 1 {
-            log!("Unexpected number of promise results");
-            return;
+    #[test]
+    fn test_is_eligible_reports_passed_and_failed_rules() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        contract.set_campaign_eligibility(
+            "campaign1".to_string(),
+            EligibilityRules {
+                min_total_donation: Some(U128(500)),
+                min_token_donations: vec![],
+                donated_after: Some(100),
+                donated_before: Some(200),
+                required_donation_types: vec![DonationType::Direct],
+                max_rewards_per_donor: Some(1),
+                excluded_accounts: vec![accounts(2)],
+            },
+        );
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+
+        // Only the donation inside the window counts towards the minimum.
+        for (timestamp, amount) in [(50, 1000), (150, 600)] {
+            testing_env!(context
+                .predecessor_account_id(accounts(1))
+                .block_timestamp(timestamp)
+                .attached_deposit(NearToken::from_yoctonear(amount))
+                .build());
+            contract.record_donation(campaign.clone(), None, None, None);
         }
+        let report = contract.is_eligible(accounts(1), "campaign1".to_string());
+        assert!(!report.eligible);
+        assert_eq!(report.failed, vec!["required_donation_types"]);
+        assert_eq!(
+            report.passed,
+            vec!["excluded_accounts", "min_total_donation", "donation_window", "max_rewards_per_donor"]
+        );
 
-        let donor = self.donors.get(&donor_id).expect("Donor not found");
-        let channel_id = donor
-            .reward_types
-            .iter()
-            .find_map(|r| match r {
-                RewardType::NFT { channel_id, .. } => Some(channel_id.clone()),
-                _ => None,
-            })
-            .expect("No NFT reward type");
+        contract.record_donation(DonationType::Direct, None, None, None);
+        assert!(contract.is_eligible(accounts(1), "campaign1".to_string()).eligible);
 
-        match env::promise_result(0) {
-            PromiseResult::Successful(result) => {
-                let token_id = String::from_utf8_lossy(&result).to_string();
-                let new_reward_type = RewardType::NFT {
-                    channel_id: channel_id.clone(),
-                    token_id: token_id.clone(),
-                };
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.log_airdrop(accounts(1), "".to_string(), campaign, U128(100));
+        assert_eq!(contract.is_eligible(accounts(1), "campaign1".to_string()).failed, vec!["max_rewards_per_donor"]);
 
-                for i in 0..self.airdrop_records.len() {
-                    let mut record = self.airdrop_records.get(i).unwrap();
-                    if
-                        record.recipient == donor_id &&
-                        matches!(record.reward_type, RewardType::NFT { .. }) &&
-                        !record.paid
-                    {
-                        record.reward_type = new_reward_type.clone();
-                        record.paid = true;
-                        self.airdrop_records.replace(i, &record);
+        let report = contract.is_eligible(accounts(2), "campaign1".to_string());
+        assert!(report.failed.contains(&"excluded_accounts".to_string()));
+    }
 
-                        let mut donor = donor.clone();
-                        // Update reward_types to include the new token_id
-                        if let Some(index) = donor.reward_types.iter().position(|r| matches!(r, RewardType::NFT { channel_id: c, .. } if c == &channel_id)) {
-                            donor.reward_types[index] = new_reward_type.clone();
-                        } else {
-                            donor.reward_types.push(new_reward_type);
-                        }
-                        donor.paid = true;
-                        self.donors.insert(&donor_id, &donor);
+    #[test]
+    #[should_panic(expected = "is not eligible for campaign campaign1: failed min_total_donation")]
+    fn test_log_airdrop_enforces_eligibility() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        contract.set_campaign_eligibility(
+            "campaign1".to_string(),
+            EligibilityRules { min_total_donation: Some(U128(500)), ..Default::default() },
+        );
 
-                        log!(
-                            "Successfully updated airdrop record for donor {} with NFT token ID {} for donation_type {}",
-                            donor_id,
-                            token_id,
-                            donor.donation_types.iter().last().map(|d| format!("{:?}", d)).unwrap_or_default()
-                        );
-                        return;
-                    }
-                }
+        contract.log_airdrop(
+            accounts(1),
+            "".to_string(),
+            DonationType::Campaign { campaign_id: "campaign1".to_string() },
+            U128(100),
+        );
+    }
 
-                log!("No matching airdrop record found for donor {}", donor_id);
-            }
-            PromiseResult::Failed => {
-                log!("NFT mint failed for donor {}", donor_id);
-            }
-        }
+    #[test]
+    #[should_panic(expected = "is not eligible for campaign campaign1: failed excluded_accounts")]
+    fn test_claim_enforces_eligibility() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        contract.log_airdrop(
+            accounts(1),
+            "".to_string(),
+            DonationType::Campaign { campaign_id: "campaign1".to_string() },
+            U128(100),
+        );
+        contract.set_campaign_eligibility(
+            "campaign1".to_string(),
+            EligibilityRules { excluded_accounts: vec![accounts(1)], ..Default::default() },
+        );
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(1)).build());
+        contract.send_token_reward();
     }
 
-    #[private]
-    pub fn on_token_transfer_callback(&mut self, donor_id: AccountId, amount: U128) {
-        if env::promise_results_count() != 1 {
-            log!("Unexpected number of promise results");
-            return;
-        }
+    #[test]
+    fn test_donation_streaks_and_multiplier() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(1);
+        contract.set_reward_policy(DonationType::Direct, RewardPolicy::Flat { amount: U128(100) });
+        contract.set_streak_config(StreakConfig {
+            period_ns: 100,
+            multipliers: vec![
+                StreakMultiplier { min_streak: 2, multiplier_bps: 15_000 },
+                StreakMultiplier { min_streak: 3, multiplier_bps: 20_000 },
+            ],
+        });
 
-        match env::promise_result(0) {
-            PromiseResult::Successful(_) => {
-                let mut donor = self.donors.get(&donor_id).expect("Donor not found");
-                for i in 0..self.airdrop_records.len() {
-                    let mut record = self.airdrop_records.get(i).unwrap();
-                    if
-                        record.recipient == donor_id &&
-                        record.amount == amount &&
-                        matches!(record.reward_type, RewardType::Token) &&
-                        !record.paid
-                    {
-                        record.paid = true;
-                        self.airdrop_records.replace(i, &record);
+        // Periods 0, 0, 1, 2, then a gap before period 5.
+        for timestamp in [10, 20, 150, 250, 550] {
+            testing_env!(context
+                .predecessor_account_id(accounts(1))
+                .block_timestamp(timestamp)
+                .attached_deposit(NearToken::from_yoctonear(1000))
+                .build());
+            contract.record_donation(DonationType::Direct, None, None, None);
+        }
+        // Nothing counts while the donations can still be refunded.
+        assert_eq!(contract.get_donor(accounts(1)).unwrap().longest_streak, 0);
 
-                        donor.paid = true;
-                        self.donors.insert(&donor_id, &donor);
+        testing_env!(context.predecessor_account_id(accounts(0)).block_timestamp(600).build());
+        let result = contract.compute_airdrops(DonationType::Direct, 10);
+        assert_eq!(result.total_amount.0, 100 + 100 + 150 + 200 + 100);
+        let streaks: Vec<u32> = contract
+            .get_donations_by_donor(accounts(1), 0, 10)
+            .donations
+            .iter()
+            .map(|d| d.streak)
+            .collect();
+        assert_eq!(streaks, vec![1, 1, 2, 3, 1]);
 
-                        log!(
-                            "Successfully transferred {} tokens to donor {} for donation_type {}",
-                            amount.0,
-                            donor_id,
-                            donor.donation_types.iter().last().map(|d| format!("{:?}", d)).unwrap_or_default()
-                        );
-                        return;
-                    }
-                }
+        let streak = contract.get_donor_streak(accounts(1));
+        assert_eq!(streak.current_streak, 1);
+        assert_eq!(streak.longest_streak, 3);
+        assert_eq!(streak.multiplier_bps, 10_000);
 
-                log!("No matching airdrop record found for donor {}", donor_id);
-            }
-            PromiseResult::Failed => {
-                log!("Token transfer failed for donor {}", donor_id);
-            }
-        }
+        // The streak lapses after a full period without a donation.
+        testing_env!(context.block_timestamp(750).build());
+        assert_eq!(contract.get_donor_streak(accounts(1)).current_streak, 0);
     }
 
-    pub fn mark_payout_complete(&mut self, donor_id: AccountId) {
-        self.assert_admin();
-        let mut donor = self.donors.get(&donor_id).expect("Donor not found");
-        assert!(!donor.paid, "Payout already completed");
-        donor.paid = true;
-        self.donors.insert(&donor_id, &donor);
+    #[test]
+    fn test_snapshot_freezes_donor_totals() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        register_test_project(&mut contract, "project1");
+        contract.set_refund_window(1_000);
+        let project = DonationType::Project { project_id: "project1".to_string() };
 
-        for i in 0..self.airdrop_records.len() {
-            let mut record = self.airdrop_records.get(i).unwrap();
-            if record.recipient == donor_id && !record.paid {
-                record.paid = true;
-                self.airdrop_records.replace(i, &record);
-                break;
-            }
+        let mut refundable = 0;
+        for (donor, donation_type) in [(1, DonationType::Direct), (1, project.clone()), (2, project.clone())] {
+            testing_env!(context
+                .predecessor_account_id(accounts(donor))
+                .attached_deposit(NearToken::from_yoctonear(1000))
+                .build());
+            refundable = contract.record_donation(donation_type, None, None, None);
         }
-        log!("Marked payout complete for donor {}", donor_id);
-    }
 
-    #[payable]
-    pub fn select_nft_reward(&mut self, channel_id: String, donation_type: DonationType) {
-        let signer = env::predecessor_account_id();
-        let mut donor = self.donors.get(&signer).expect("Donor not found");
-        assert!(
-            donor.reward_types.contains(&RewardType::Token),
-            "Donor reward type does not include Token"
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        let snapshot = contract.take_snapshot("round 1".to_string());
+        assert_eq!(snapshot.donation_cutoff, 3);
+
+        // A late donation does not change the snapshot; a refund takes the donation back out.
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(NearToken::from_yoctonear(500)).build());
+        contract.record_donation(project.clone(), None, None, None);
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(10)
+            .attached_deposit(NearToken::from_yoctonear(0))
+            .build());
+        contract.refund_donation(refundable);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        assert!(!contract.build_snapshot(snapshot.id, 2).complete);
+        let snapshot = contract.build_snapshot(snapshot.id, 2);
+        assert!(snapshot.complete);
+        assert_eq!(snapshot.donor_count, 1);
+        assert_eq!(snapshot.total_donated.0, 2000);
+
+        let balance = contract.get_snapshot_balance(snapshot.id, accounts(1));
+        assert_eq!(
+            balance.amounts,
+            vec![
+                DonationTypeAmount { donation_type: DonationType::Direct, amount: U128(1000) },
+                DonationTypeAmount { donation_type: project.clone(), amount: U128(1000) },
+            ]
         );
-        assert!(!donor.paid, "Payout already completed");
-        match &donation_type {
-            DonationType::Project { project_id } => assert!(!project_id.is_empty(), "Project ID must not be empty"),
-            DonationType::Campaign { campaign_id } => assert!(campaign_id.len() <= 64, "Campaign ID must be 64 characters or less"),
-            DonationType::Pot { pot_id } => assert!(env::is_valid_account_id(pot_id.as_bytes()), "Invalid pot_id"),
-            DonationType::Direct => (),
-        }
+        assert_eq!(contract.get_snapshot_balances(snapshot.id, 0, 10).balances.len(), 1);
 
-        let new_reward_type = RewardType::NFT {
-            channel_id: channel_id.clone(),
-            token_id: String::new(),
-        };
+        let distribution = contract.start_snapshot_distribution(snapshot.id, project.clone(), U128(100), DistributionMode::ProRata);
+        assert_eq!(distribution.donation_cutoff, 1);
+        let distribution = contract.process_distribution(project, 10);
+        assert_eq!(distribution.status, DistributionStatus::Complete);
+        assert_eq!(distribution.donor_count, 1);
+        assert_eq!(distribution.distributed.0, 100);
+        assert!(contract.get_airdrop_records(0, 10).records.iter().all(|r| r.recipient == accounts(1)));
+    }
 
-        // Add donation_type if not already present
-        if !donor.donation_types.contains(&donation_type) {
-            donor.donation_types.push(donation_type.clone());
+    #[test]
+    fn test_rounds_leave_out_donations_compute_airdrops_rewarded() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(5);
+        contract.set_reward_policy(DonationType::Direct, RewardPolicy::Ratio { ratio_bps: 5_000 });
+        for donor in 1..3 {
+            testing_env!(context.predecessor_account_id(accounts(donor)).attached_deposit(NearToken::from_yoctonear(1000)).build());
+            contract.record_donation(DonationType::Direct, None, None, None);
         }
 
-        // Add reward_type if not already present
-        if !donor.reward_types.contains(&new_reward_type) {
-            donor.reward_types.push(new_reward_type);
-        }
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).block_timestamp(10).build());
+        assert_eq!(contract.compute_airdrops(DonationType::Direct, 10).airdrops_created, 2);
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(NearToken::from_yoctonear(1000)).build());
+        contract.record_donation(DonationType::Direct, None, None, None);
 
-        self.donors.insert(&signer, &donor);
-        log!("Donor {} selected NFT reward with channel_id {} for donation_type {:?}", signer, channel_id, donation_type);
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        let round = contract.finalize_round(DonationType::Direct, U128(100));
+        assert_eq!((round.donor_count, round.total_contributed.0), (1, 1000));
+        let records = contract.get_airdrop_records(0, 10).records;
+        assert_eq!(records.len(), 3);
+        assert_eq!((records[2].recipient.clone(), records[2].amount.0), (accounts(3), 100));
     }
 
-    pub fn get_donor(&self, wallet_id: AccountId) -> Option<Donor> {
-        self.donors.get(&wallet_id)
-    }
+    #[test]
+    fn test_snapshot_rounds_and_compute_airdrops_reward_donations_once() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(5);
+        contract.set_reward_policy(DonationType::Direct, RewardPolicy::Ratio { ratio_bps: 5_000 });
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(1000)).build());
+        contract.record_donation(DonationType::Direct, None, None, None);
 
-    pub fn get_donors(&self, start: u64, limit: u64) -> PaginatedDonors {
-        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
-        let donors: Vec<Donor> = self.donors
-            .values()
-            .skip(start as usize)
-            .take(limit as usize)
-            .collect();
-        let has_more = self.donors.len() > start + limit;
-        PaginatedDonors { donors, has_more }
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).block_timestamp(10).build());
+        assert_eq!(contract.compute_airdrops(DonationType::Direct, 10).airdrops_created, 1);
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(3000)).build());
+        contract.record_donation(DonationType::Direct, None, None, None);
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(NearToken::from_yoctonear(1000)).build());
+        contract.record_donation(DonationType::Direct, None, None, None);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        let snapshot = contract.take_snapshot("round 1".to_string());
+        contract.build_snapshot(snapshot.id, 10);
+        contract.start_snapshot_distribution(snapshot.id, DonationType::Direct, U128(100), DistributionMode::ProRata);
+        let distribution = contract.process_distribution(DonationType::Direct, 10);
+        // The first donation of accounts(1) was already rewarded by `compute_airdrops`.
+        assert_eq!((distribution.donor_count, distribution.total_contributed.0), (2, 4000));
+
+        testing_env!(context.block_timestamp(20).build());
+        let result = contract.compute_airdrops(DonationType::Direct, 10);
+        assert_eq!((result.airdrops_created, result.next_index), (0, 3));
     }
 
-    pub fn get_donors_by_donation_type(&self, donation_type: DonationType, start: u64, limit: u64) -> PaginatedDonors {
-        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
-        let donors: Vec<Donor> = self.donors
-            .values()
-            .filter(|donor| donor.donation_types.contains(&donation_type))
-            .skip(start as usize)
-            .take(limit as usize)
-            .collect();
-        let total_matching = self
-            .donors
-            .values()
-            .filter(|donor| donor.donation_types.contains(&donation_type))
-            .count() as u64;
-        let has_more = total_matching > start + limit;
-        PaginatedDonors { donors, has_more }
+    #[test]
+    fn test_refund_after_snapshot_is_built_leaves_donor_out() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(1_000);
+
+        let mut refundable = 0;
+        for donor in [1, 2] {
+            testing_env!(context
+                .predecessor_account_id(accounts(donor))
+                .attached_deposit(NearToken::from_yoctonear(1000))
+                .build());
+            refundable = contract.record_donation(DonationType::Direct, None, None, None);
+        }
+
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        let snapshot = contract.take_snapshot("round 1".to_string());
+        assert!(contract.build_snapshot(snapshot.id, 10).complete);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).block_timestamp(10).build());
+        contract.refund_donation(refundable);
+        assert!(contract.get_snapshot_balance(snapshot.id, accounts(2)).amounts.iter().all(|a| a.amount.0 == 0));
+        assert_eq!(contract.get_snapshot(snapshot.id).unwrap().total_donated.0, 1000);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.start_snapshot_distribution(snapshot.id, DonationType::Direct, U128(100), DistributionMode::ProRata);
+        let distribution = contract.process_distribution(DonationType::Direct, 10);
+        assert_eq!(distribution.status, DistributionStatus::Complete);
+        assert_eq!(distribution.donor_count, 1);
+        let records = contract.get_airdrop_records(0, 10).records;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].recipient, accounts(1));
+        assert_eq!(records[0].amount.0, 100);
     }
 
-    pub fn get_airdrop_records(&self, start: u64, limit: u64) -> PaginatedAirdropRecords {
-        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
-        let records: Vec<AirdropRecord> = self.airdrop_records
-            .iter()
-            .skip(start as usize)
-            .take(limit as usize)
-            .collect();
-        let has_more = self.airdrop_records.len() > start + limit;
-        PaginatedAirdropRecords { records, has_more }
+    #[test]
+    #[should_panic(expected = "Snapshot is still being built")]
+    fn test_snapshot_distribution_requires_complete_snapshot() {
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build();
+        testing_env!(context);
+        let mut contract = DonorPayouts::new(None, None);
+        contract.record_donation(DonationType::Direct, None, None, None);
+
+        let snapshot = contract.take_snapshot("round 1".to_string());
+        contract.start_snapshot_distribution(snapshot.id, DonationType::Direct, U128(100), DistributionMode::Quadratic);
     }
 
-    pub fn get_airdrop_records_by_donation_type(
-        &self,
-        donation_type: DonationType,
-        start: u64,
-        limit: u64
-    ) -> PaginatedAirdropRecords {
-        assert!(limit > 0 && limit <= 100, "Limit must be between 1 and 100");
-        let records: Vec<AirdropRecord> = self.airdrop_records
-            .iter()
-            .filter(|record| record.donation_type == donation_type)
-            .skip(start as usize)
-            .take(limit as usize)
-            .collect();
-        let total_matching = self.airdrop_records
-            .iter()
-            .filter(|record| record.donation_type == donation_type)
-            .count() as u64;
-        let has_more = total_matching > start + limit;
-        PaginatedAirdropRecords { records, has_more }
+    #[test]
+    fn test_log_airdrop_tracks_campaign_budget() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        contract.set_campaign_budget(
+            "campaign1".to_string(),
+            U128(1_000),
+            Some(2),
+            RewardCaps { per_donor_tokens: Some(U128(600)), per_donor_nfts: Some(1) },
+        );
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+
+        contract.log_airdrop(accounts(1), "".to_string(), campaign.clone(), U128(600));
+        contract.log_airdrop(accounts(2), "".to_string(), campaign.clone(), U128(400));
+        contract.log_airdrop(accounts(1), "channel".to_string(), campaign.clone(), U128(1));
+
+        let budget = contract.get_campaign_budget("campaign1".to_string());
+        assert_eq!(budget.tokens_allocated.0, 1_000);
+        assert_eq!(budget.tokens_remaining.0, 0);
+        assert_eq!(budget.nfts_allocated, 1);
+        assert_eq!(budget.nfts_remaining, Some(1));
+        let rewards = contract.get_campaign_rewards("campaign1".to_string(), accounts(1));
+        assert_eq!((rewards.record_count, rewards.tokens.0, rewards.nfts), (2, 600, 1));
     }
 
-    pub fn get_project_rewards(&self, project_id: String) -> (U128, U128) {
-        let total_donations = self.donors
-            .values()
-            .filter(|donor| donor.donation_types.iter().any(|d| matches!(d, DonationType::Project { project_id: ref id } if id == &project_id)))
-            .map(|donor| donor.donation_amount.0)
-            .sum::<u128>();
-        let total_airdropped = self.airdrop_records
-            .iter()
-            .filter(|record| matches!(record.donation_type, DonationType::Project { project_id: ref id } if id == &project_id))
-            .map(|record| record.amount.0)
-            .sum::<u128>();
-        (U128(total_donations), U128(total_airdropped))
+    #[test]
+    #[should_panic(expected = "Airdrop exceeds the campaign token budget, 100 remaining")]
+    fn test_log_airdrop_rejects_over_token_budget() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+
+        contract.log_airdrop(accounts(1), "".to_string(), campaign.clone(), U128(900));
+        contract.log_airdrop(accounts(2), "".to_string(), campaign, U128(101));
     }
 
-    pub fn get_total_distributed(&self) -> U128 {
-        U128(self.total_distributed)
+    #[test]
+    #[should_panic(expected = "Airdrop exceeds the campaign token budget, 990 remaining")]
+    fn test_log_airdrop_rejects_overflowing_token_amount() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+
+        contract.log_airdrop(accounts(1), "".to_string(), campaign.clone(), U128(10));
+        contract.log_airdrop(accounts(2), "".to_string(), campaign, U128(u128::MAX - 9));
     }
 
-    pub fn get_donor_count(&self) -> u64 {
-        self.donors.len()
+    #[test]
+    #[should_panic(expected = "Airdrop exceeds the per-donor NFT cap")]
+    fn test_log_airdrop_rejects_over_per_donor_cap() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        contract.set_campaign_budget(
+            "campaign1".to_string(),
+            U128(1_000),
+            None,
+            RewardCaps { per_donor_tokens: None, per_donor_nfts: Some(1) },
+        );
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+
+        contract.log_airdrop(accounts(1), "channel".to_string(), campaign.clone(), U128(1));
+        contract.log_airdrop(accounts(1), "channel".to_string(), campaign, U128(1));
     }
-}
 
+    #[test]
+    #[should_panic(expected = "Token budget is below the tokens already allocated")]
+    fn test_set_campaign_budget_below_allocated() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+        contract.log_airdrop(accounts(1), "".to_string(), campaign, U128(500));
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use near_sdk::test_utils::{VMContextBuilder, accounts};
-    use near_sdk::testing_env;
+        contract.set_campaign_budget("campaign1".to_string(), U128(400), None, RewardCaps::default());
+    }
 
     #[test]
-    fn test_log_airdrop_multiple_donation_and_reward_types() {
-        let context = VMContextBuilder::new()
-            .predecessor_account_id(accounts(0))
-            .attached_deposit(NearToken::from_yoctonear(1000))
-            .build();
-        testing_env!(context);
+    fn test_compute_airdrops_respects_campaign_caps() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
         let mut contract = DonorPayouts::new(None, None);
-
-       
-        contract.log_airdrop(
-            accounts(1),
-            "".to_string(),
-            DonationType::Campaign { campaign_id: "campaign1".to_string() },
-            U128(1),
+        create_test_campaign(&mut contract, "campaign1");
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+        contract.set_reward_policy(campaign.clone(), RewardPolicy::Ratio { ratio_bps: 10_000 });
+        contract.set_campaign_budget(
+            "campaign1".to_string(),
+            U128(500),
+            None,
+            RewardCaps { per_donor_tokens: Some(U128(300)), per_donor_nfts: None },
         );
 
-      
-        let mut context = VMContextBuilder::new()
-            .predecessor_account_id(accounts(0))
-            .build();
-        context.attached_deposit = NearToken::from_yoctonear(2000);
-        testing_env!(context);
-
-       
-        contract.log_airdrop(
-            accounts(1),
-            "channel123".to_string(),
-            DonationType::Pot { pot_id: accounts(2) },
-            U128(2),
-        );
+        for (donor, amount) in [(1, 500), (1, 500), (2, 800)] {
+            testing_env!(context
+                .predecessor_account_id(accounts(donor))
+                .attached_deposit(NearToken::from_yoctonear(amount))
+                .build());
+            let donation_id = contract.record_donation(campaign.clone(), None, None, None);
+            callback_context(PromiseResult::Successful(vec![]));
+            contract.on_donation_forwarded(donation_id, accounts(4), vec![]);
+        }
 
-        let donor = contract.get_donor(accounts(1)).unwrap();
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        let result = contract.compute_airdrops(campaign, 10);
+        assert_eq!((result.airdrops_created, result.total_amount.0), (2, 500));
+        assert_eq!(contract.get_campaign_rewards("campaign1".to_string(), accounts(1)).tokens.0, 300);
+        assert_eq!(contract.get_campaign_rewards("campaign1".to_string(), accounts(2)).tokens.0, 200);
+        assert_eq!(contract.get_campaign_budget("campaign1".to_string()).tokens_remaining.0, 0);
+    }
 
-        assert_eq!(donor.wallet_id, accounts(1));
-        assert_eq!(donor.airdrop_amount, U128(3));
-        assert_eq!(donor.donation_amount, U128(3000));
-        assert_eq!(donor.paid, false);
+    #[test]
+    #[should_panic(expected = "Distribution budget exceeds the campaign token budget, 100 remaining")]
+    fn test_distribution_rejects_budget_over_campaign_remaining() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+        contract.log_airdrop(accounts(1), "".to_string(), campaign.clone(), U128(900));
 
-       
-        assert_eq!(donor.donation_types.len(), 2);
-        assert!(donor.donation_types.contains(&DonationType::Campaign { campaign_id: "campaign1".to_string() }));
-        assert!(donor.donation_types.contains(&DonationType::Pot { pot_id: accounts(2) }));
+        contract.start_quadratic_distribution(campaign, U128(200));
+    }
 
-       
-        assert_eq!(donor.reward_types.len(), 2);
-        assert!(donor.reward_types.contains(&RewardType::Token));
-        assert!(donor.reward_types.iter().any(|r| matches!(r, RewardType::NFT { channel_id, token_id } if channel_id == "channel123" && token_id == "")));
+    #[test]
+    fn test_distribution_shares_respect_per_donor_cap() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+        contract.set_campaign_budget(
+            "campaign1".to_string(),
+            U128(1_000),
+            None,
+            RewardCaps { per_donor_tokens: Some(U128(300)), per_donor_nfts: None },
+        );
 
-        assert_eq!(contract.get_total_distributed(), U128(3));
+        for donor in [1, 2] {
+            testing_env!(context
+                .predecessor_account_id(accounts(donor))
+                .attached_deposit(NearToken::from_yoctonear(1_000))
+                .build());
+            let donation_id = contract.record_donation(campaign.clone(), None, None, None);
+            callback_context(PromiseResult::Successful(vec![]));
+            contract.on_donation_forwarded(donation_id, accounts(4), vec![]);
+        }
 
-        let records = contract.get_airdrop_records(0, 2).records;
-        assert_eq!(records.len(), 2);
-        assert_eq!(records[0].recipient, accounts(1));
-        assert_eq!(records[0].amount, U128(1));
-        assert_eq!(records[1].recipient, accounts(1));
-        assert_eq!(records[1].amount, U128(2));
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        let distribution = contract.finalize_round(campaign, U128(1_000));
+        assert_eq!(distribution.status, DistributionStatus::Complete);
+        assert_eq!((distribution.distributed.0, distribution.dust.0), (600, 400));
+        for donor in [1, 2] {
+            assert_eq!(contract.get_campaign_rewards("campaign1".to_string(), accounts(donor)).tokens.0, 300);
+        }
+        assert_eq!(contract.get_campaign_budget("campaign1".to_string()).tokens_allocated.0, 600);
     }
 
     #[test]
-    fn test_record_donation_multiple_types() {
-        let context = VMContextBuilder::new()
+    fn test_preview_reward_applies_policy_and_next_streak() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_reward_policy(DonationType::Direct, RewardPolicy::Ratio { ratio_bps: 5_000 });
+        contract.set_streak_config(StreakConfig {
+            period_ns: 100,
+            multipliers: vec![StreakMultiplier { min_streak: 2, multiplier_bps: 15_000 }],
+        });
+
+        testing_env!(context
             .predecessor_account_id(accounts(1))
+            .block_timestamp(50)
             .attached_deposit(NearToken::from_yoctonear(1000))
-            .build();
-        testing_env!(context);
-        let mut contract = DonorPayouts::new(None, None);
+            .build());
+        let donation_id = contract.record_donation(DonationType::Direct, None, None, None);
+        callback_context(PromiseResult::Successful(vec![]));
+        contract.on_donation_forwarded(donation_id, accounts(0), vec![]);
 
-       
-        contract.record_donation(DonationType::Direct);
+        // Donating again next period would extend the streak to 2.
+        testing_env!(context.block_timestamp(150).attached_deposit(NearToken::from_yoctonear(0)).build());
+        let preview = contract.preview_reward(accounts(1), DonationType::Direct, U128(1000));
+        assert_eq!(preview.rejection, None);
+        assert_eq!(preview.accepted_amount.0, 1000);
+        assert_eq!(preview.base_reward.0, 500);
+        assert_eq!((preview.streak, preview.multiplier_bps), (2, 15_000));
+        assert_eq!(preview.reward.0, 750);
+        assert!(!preview.capped);
+        assert_eq!(contract.get_donor_streak(accounts(1)).current_streak, 1);
 
+        let newcomer = contract.preview_reward(accounts(2), DonationType::Direct, U128(1000));
+        assert_eq!((newcomer.streak, newcomer.reward.0), (1, 500));
+    }
 
-        let mut context = VMContextBuilder::new()
-            .predecessor_account_id(accounts(1))
-            .build();
-        context.attached_deposit = NearToken::from_yoctonear(2000);
-        testing_env!(context);
-        contract.record_donation(DonationType::Project { project_id: "project1".to_string() });
+    #[test]
+    fn test_preview_reward_respects_campaign_caps() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+        contract.set_reward_policy(campaign.clone(), RewardPolicy::Ratio { ratio_bps: 10_000 });
+        contract.set_campaign_budget(
+            "campaign1".to_string(),
+            U128(1_000),
+            None,
+            RewardCaps { per_donor_tokens: Some(U128(300)), per_donor_nfts: None },
+        );
+        contract.log_airdrop(accounts(1), "".to_string(), campaign.clone(), U128(100));
 
-        let donor = contract.get_donor(accounts(1)).unwrap();
+        let preview = contract.preview_reward(accounts(1), campaign.clone(), U128(5_000));
+        assert_eq!(preview.base_reward.0, 5_000);
+        assert!(preview.capped);
+        assert_eq!(preview.reward.0, 200);
 
-        assert_eq!(donor.donation_amount, U128(3000)); 
-        assert_eq!(donor.donation_types.len(), 2);
-        assert!(donor.donation_types.contains(&DonationType::Direct));
-        assert!(donor.donation_types.contains(&DonationType::Project { project_id: "project1".to_string() }));
+        testing_env!(context.block_timestamp(2_000_000).build());
+        let preview = contract.preview_reward(accounts(1), campaign, U128(5_000));
+        assert_eq!(preview.rejection.as_deref(), Some("Campaign is not accepting donations"));
+        assert_eq!(preview.reward.0, 0);
     }
 
     #[test]
-    fn test_select_nft_reward_adds_types() {
-        let context = VMContextBuilder::new()
-            .predecessor_account_id(accounts(0))
-            .attached_deposit(NearToken::from_yoctonear(1000))
-            .build();
-        testing_env!(context);
+    fn test_preview_reward_applies_eligibility_and_human_check() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
         let mut contract = DonorPayouts::new(None, None);
-
-      
-        contract.log_airdrop(
-            accounts(1),
-            "".to_string(),
-            DonationType::Campaign { campaign_id: "campaign1".to_string() },
-            U128(1),
+        create_test_campaign(&mut contract, "campaign1");
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+        contract.set_reward_policy(campaign.clone(), RewardPolicy::Ratio { ratio_bps: 10_000 });
+        contract.set_campaign_eligibility(
+            "campaign1".to_string(),
+            EligibilityRules { excluded_accounts: vec![accounts(2)], ..Default::default() },
         );
+        set_test_human_registry(&mut contract, HumanCheckMode::Reject);
 
-      
-        let mut context = VMContextBuilder::new()
-            .build();
-        context.predecessor_account_id = accounts(1);
-        testing_env!(context);
-        contract.select_nft_reward("channel123".to_string(), DonationType::Direct);
+        let excluded = contract.preview_reward(accounts(2), campaign.clone(), U128(1_000));
+        assert_eq!(excluded.rejection, None);
+        assert_eq!(excluded.accepted_amount.0, 1_000);
+        assert!(excluded.reward_rejection.unwrap().contains("failed excluded_accounts"));
+        assert_eq!(excluded.reward.0, 0);
 
-        let donor = contract.get_donor(accounts(1)).unwrap();
+        let unverified = contract.preview_reward(accounts(1), campaign.clone(), U128(1_000));
+        assert_eq!(
+            unverified.reward_rejection.as_deref(),
+            Some("Account is not a verified human, call verify_human first")
+        );
+        assert_eq!(unverified.reward.0, 0);
 
-        assert_eq!(donor.donation_types.len(), 2);
-        assert!(donor.donation_types.contains(&DonationType::Campaign { campaign_id: "campaign1".to_string() }));
-        assert!(donor.donation_types.contains(&DonationType::Direct));
+        mock_registry_answer(&mut contract, accounts(1), true, 0);
+        let verified = contract.preview_reward(accounts(1), campaign, U128(1_000));
+        assert_eq!(verified.reward_rejection, None);
+        assert_eq!(verified.reward.0, 1_000);
+    }
 
-        assert_eq!(donor.reward_types.len(), 2);
-        assert!(donor.reward_types.contains(&RewardType::Token));
-        assert!(donor.reward_types.iter().any(|r| matches!(r, RewardType::NFT { channel_id, token_id } if channel_id == "channel123" && token_id == "")));
+    fn airdrop_input(recipient: AccountId, donation_type: DonationType, amount: u128, deposit: u128) -> AirdropInput {
+        AirdropInput {
+            recipient,
+            channel_id: "".to_string(),
+            donation_type,
+            amount: U128(amount),
+            deposit: U128(deposit),
+        }
     }
 
     #[test]
-    fn test_get_donors_by_donation_type() {
+    fn test_log_airdrops_reports_applied_and_rejected_entries() {
         let context = VMContextBuilder::new()
             .predecessor_account_id(accounts(0))
-            .attached_deposit(NearToken::from_yoctonear(1000))
+            .attached_deposit(NearToken::from_yoctonear(1_000))
             .build();
         testing_env!(context);
         let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
 
-        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Campaign { campaign_id: "campaign1".to_string() }, U128(1));
-        let mut context = VMContextBuilder::new()
-            .predecessor_account_id(accounts(0))
-            .build();
-        context.attached_deposit = NearToken::from_yoctonear(2000);
-        testing_env!(context);
-        contract.log_airdrop(accounts(1), "channel123".to_string(), DonationType::Project { project_id: "project1".to_string() }, U128(1));
-        contract.log_airdrop(accounts(2), "".to_string(), DonationType::Project { project_id: "project1".to_string() }, U128(1));
+        let result = contract.log_airdrops(vec![
+            airdrop_input(accounts(1), campaign.clone(), 800, 300),
+            airdrop_input(accounts(2), campaign.clone(), 300, 200),
+            airdrop_input(accounts(2), DonationType::Project { project_id: "missing".to_string() }, 10, 0),
+            airdrop_input(accounts(3), DonationType::Direct, 50, 100),
+        ]);
 
-        let result = contract.get_donors_by_donation_type(DonationType::Campaign { campaign_id: "campaign1".to_string() }, 0, 1);
-        assert_eq!(result.donors.len(), 1);
-        assert_eq!(result.donors[0].wallet_id, accounts(1));
-        assert_eq!(result.donors[0].donation_amount, U128(3000)); 
-        assert!(!result.has_more);
+        assert_eq!(result.applied, vec![0, 3]);
+        assert_eq!(
+            result.rejected,
+            vec![
+                AirdropRejection { index: 1, reason: "Airdrop exceeds the campaign token budget, 200 remaining".to_string() },
+                AirdropRejection { index: 2, reason: "Project not found".to_string() },
+            ]
+        );
+        assert_eq!(result.next_index, 4);
+        assert_eq!(result.refunded.0, 600);
+        assert_eq!(contract.get_airdrop_records(0, 10).records.len(), 2);
+        assert_eq!(contract.get_donor(accounts(1)).unwrap().donation_amount.0, 300);
+        assert!(contract.get_donor(accounts(2)).is_none());
+        assert_eq!(contract.get_campaign_budget("campaign1".to_string()).tokens_allocated.0, 800);
 
-        let result = contract.get_donors_by_donation_type(DonationType::Project { project_id: "project1".to_string() }, 0, 2);
-        assert_eq!(result.donors.len(), 2);
-        assert_eq!(result.donors[0].wallet_id, accounts(1));
-        assert_eq!(result.donors[1].wallet_id, accounts(2));
-        assert!(!result.has_more);
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, accounts(0));
     }
 
     #[test]
-    fn test_get_airdrop_records() {
+    fn test_log_airdrops_stops_when_gas_runs_low() {
         let context = VMContextBuilder::new()
             .predecessor_account_id(accounts(0))
-            .attached_deposit(NearToken::from_yoctonear(1000))
+            .attached_deposit(NearToken::from_yoctonear(100))
+            .prepaid_gas(Gas::from_tgas(9))
             .build();
         testing_env!(context);
         let mut contract = DonorPayouts::new(None, None);
 
-        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Direct, U128(1));
-        contract.log_airdrop(accounts(2), "channel123".to_string(), DonationType::Pot { pot_id: accounts(3) }, U128(1));
-
-        let result = contract.get_airdrop_records(0, 1);
-        assert_eq!(result.records.len(), 1);
-        assert_eq!(result.records[0].recipient, accounts(1));
-        assert!(result.has_more);
-
-        let result = contract.get_airdrop_records(1, 1);
-        assert_eq!(result.records.len(), 1);
-        assert!(!result.has_more);
+        let result = contract.log_airdrops(vec![airdrop_input(accounts(1), DonationType::Direct, 50, 100)]);
+        assert!(result.applied.is_empty() && result.rejected.is_empty());
+        assert_eq!(result.next_index, 0);
+        assert_eq!(result.refunded.0, 100);
+        assert!(contract.get_donor(accounts(1)).is_none());
     }
 
     #[test]
-    fn test_get_project_rewards() {
+    fn test_log_airdrops_resumes_from_next_index() {
+        let entries: Vec<AirdropInput> =
+            (1..6).map(|i| airdrop_input(accounts(i % 5), DonationType::Direct, 50, 100)).collect();
         let context = VMContextBuilder::new()
             .predecessor_account_id(accounts(0))
-            .attached_deposit(NearToken::from_yoctonear(1000))
+            .attached_deposit(NearToken::from_yoctonear(500))
+            .prepaid_gas(Gas::from_tgas(12))
             .build();
         testing_env!(context);
         let mut contract = DonorPayouts::new(None, None);
 
-        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Project { project_id: "project1".to_string() }, U128(1));
-        let mut context = VMContextBuilder::new()
+        let first = contract.log_airdrops(entries.clone());
+        let next_index = first.next_index as usize;
+        assert!(next_index > 0 && next_index < entries.len());
+        assert_eq!(first.applied, (0..first.next_index).collect::<Vec<_>>());
+        assert_eq!(first.refunded.0, 100 * (entries.len() - next_index) as u128);
+
+        let remaining = entries.len() - next_index;
+        let context = VMContextBuilder::new()
             .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(100 * remaining as u128))
             .build();
-        context.attached_deposit = NearToken::from_yoctonear(2000);
         testing_env!(context);
-        contract.log_airdrop(accounts(2), "".to_string(), DonationType::Project { project_id: "project1".to_string() }, U128(1));
+        let second = contract.log_airdrops(entries[next_index..].to_vec());
+        assert_eq!(second.next_index as usize, remaining);
+        assert_eq!(second.applied.len(), remaining);
+        assert_eq!(second.refunded.0, 0);
+        assert_eq!(contract.get_airdrop_records(0, 10).records.len(), entries.len());
+    }
 
-        let (total_donations, total_airdropped) = contract.get_project_rewards("project1".to_string());
-        assert_eq!(total_donations, U128(3000));
-        assert_eq!(total_airdropped, U128(2));
+    #[test]
+    #[should_panic(expected = "Entry deposits overflow")]
+    fn test_log_airdrops_rejects_overflowing_deposits() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+
+        contract.log_airdrops(vec![
+            airdrop_input(accounts(1), DonationType::Direct, 50, u128::MAX),
+            airdrop_input(accounts(2), DonationType::Direct, 50, 1),
+        ]);
     }
 
     #[test]
-    #[should_panic(expected = "Campaign ID must be 64 characters or less")]
-    fn test_log_airdrop_invalid_campaign_id() {
+    #[should_panic(expected = "Attached deposit must cover the deposits of all entries")]
+    fn test_log_airdrops_requires_entry_deposits() {
         let context = VMContextBuilder::new()
             .predecessor_account_id(accounts(0))
-            .attached_deposit(NearToken::from_yoctonear(1000))
+            .attached_deposit(NearToken::from_yoctonear(100))
             .build();
         testing_env!(context);
         let mut contract = DonorPayouts::new(None, None);
 
-        let long_campaign_id = "a".repeat(65);
-        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Campaign { campaign_id: long_campaign_id }, U128(1));
+        contract.log_airdrops(vec![
+            airdrop_input(accounts(1), DonationType::Direct, 50, 60),
+            airdrop_input(accounts(2), DonationType::Direct, 50, 60),
+        ]);
     }
 
     #[test]
-    #[should_panic(expected = "Donor not found")]
-    fn test_select_nft_reward_no_donor() {
+    fn test_migrate_from_first_release() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut donors = UnorderedMap::new(b"d");
+        donors.insert(
+            &accounts(1),
+            &DonorV0 {
+                wallet_id: accounts(1),
+                donation_amount: U128(500),
+                airdrop_amount: U128(30),
+                paid: false,
+                reward_types: vec![RewardType::Token],
+                donation_types: vec![DonationType::Direct],
+            },
+        );
+        let mut airdrop_records = Vector::new(b"a");
+        for (amount, paid) in [(10, true), (20, false)] {
+            airdrop_records.push(&AirdropRecordV0 {
+                recipient: accounts(1),
+                amount: U128(amount),
+                timestamp: 0,
+                paid,
+                reward_type: RewardType::Token,
+                donation_type: DonationType::Direct,
+            });
+        }
+        env::state_write(&DonorPayoutsV0 {
+            donors,
+            airdrop_records,
+            total_distributed: 30,
+            admin: accounts(0),
+            potlock_nfts_contract: accounts(2),
+            token_contract: accounts(3),
+        });
+
+        let mut contract = DonorPayouts::migrate();
+        let donor = contract.get_donor(accounts(1)).unwrap();
+        assert_eq!((donor.donation_amount.0, donor.airdrop_amount.0), (500, 30));
+        assert_eq!(donor.donation_types, vec![DonationType::Direct]);
+        let records = contract.get_airdrop_records(0, 10).records;
+        assert_eq!(records.len(), 2);
+        assert!(records[0].paid && records[1].donation_id.is_none());
+        let stats = contract.get_stats();
+        assert_eq!((stats.token_rewards.paid_amount.0, stats.token_rewards.unpaid_amount.0), (10, 20));
+        assert_eq!(contract.get_total_distributed().0, 30);
+        assert_eq!(contract.get_treasury(), accounts(0));
+
+        // The migrated contract accepts new writes.
+        contract.log_airdrop(accounts(1), "".to_string(), DonationType::Direct, U128(5));
+        assert_eq!(contract.get_donor(accounts(1)).unwrap().airdrop_amount.0, 35);
+    }
+
+    #[test]
+    fn test_failed_fee_transfers_become_withdrawable() {
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(0)).build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_fees(200, 100);
+        contract.set_treasury(accounts(3));
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(10_000))
+            .build());
+        let donation_id = contract.record_donation(DonationType::Direct, None, Some(accounts(2)), None);
+
+        // The donation and protocol fee arrive, the referrer's fee does not.
+        callback_results(vec![
+            PromiseResult::Successful(vec![]),
+            PromiseResult::Successful(vec![]),
+            PromiseResult::Failed,
+        ]);
+        contract.on_donation_forwarded(
+            donation_id,
+            accounts(3),
+            vec![(accounts(3), U128(200)), (accounts(2), U128(100))],
+        );
+        assert_eq!(contract.get_donation(donation_id).unwrap().status, DonationStatus::Forwarded);
+        assert_eq!(contract.get_unclaimed_fees(accounts(3)).near.0, 0);
+        assert_eq!(contract.get_unclaimed_fees(accounts(2)).near.0, 100);
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(accounts(2)).build());
+        contract.withdraw_fees(None);
+        assert_eq!(contract.get_unclaimed_fees(accounts(2)).near.0, 0);
+
+        // A failed withdrawal is credited back.
+        callback_context(PromiseResult::Failed);
+        contract.on_fees_withdrawn(accounts(2), None, U128(100));
+        assert_eq!(contract.get_unclaimed_fees(accounts(2)).near.0, 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid referrer_id")]
+    fn test_record_donation_rejects_contract_as_referrer() {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(1_000))
+            .build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.record_donation(DonationType::Direct, None, Some(accounts(0)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Interval must be between 1 and")]
+    fn test_recurring_interval_is_bounded() {
         let context = VMContextBuilder::new()
             .predecessor_account_id(accounts(1))
             .attached_deposit(NearToken::from_yoctonear(1000))
             .build();
         testing_env!(context);
         let mut contract = DonorPayouts::new(None, None);
+        contract.create_recurring_donation(U128(100), u64::MAX, DonationType::Direct);
+    }
 
-        contract.select_nft_reward("channel123".to_string(), DonationType::Direct);
+    #[test]
+    #[should_panic(expected = "Donation has already been used for an airdrop")]
+    fn test_rewarded_held_donation_cannot_be_reclaimed() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(1000));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(100);
+        contract.set_reward_policy(DonationType::Direct, RewardPolicy::Ratio { ratio_bps: 5_000 });
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let donation_id = contract.record_donation(DonationType::Direct, None, None, None);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).block_timestamp(120).build());
+        contract.compute_airdrops(DonationType::Direct, 10);
+        assert!(contract.get_donation(donation_id).unwrap().rewarded);
+        contract.release_donation(donation_id);
+        callback_context(PromiseResult::Failed);
+        contract.on_donation_forwarded(donation_id, accounts(0), vec![]);
+        assert_eq!(contract.get_donation(donation_id).unwrap().status, DonationStatus::Held);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.refund_held_donation(donation_id);
+    }
+
+    #[test]
+    fn test_rounds_count_only_donations_since_the_previous_round() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(1_000);
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(1000)).build());
+        contract.record_donation(DonationType::Direct, None, None, None);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        let first = contract.finalize_round(DonationType::Direct, U128(100));
+        assert_eq!(first.status, DistributionStatus::Complete);
+
+        // Donations after the round stay refundable and go into the next one.
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(NearToken::from_yoctonear(1000)).build());
+        let refunded_id = contract.record_donation(DonationType::Direct, None, None, None);
+        contract.refund_donation(refunded_id);
+        testing_env!(context.attached_deposit(NearToken::from_yoctonear(3000)).build());
+        contract.record_donation(DonationType::Direct, None, None, None);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        let second = contract.finalize_round(DonationType::Direct, U128(100));
+        assert_eq!(second.round, 1);
+        assert_eq!(second.status, DistributionStatus::Complete);
+        assert_eq!(second.donor_count, 1);
+        assert_eq!(second.total_contributed.0, 3000);
+
+        let records = contract.get_airdrop_records(0, 10).records;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].recipient, accounts(2));
+        assert_eq!(records[1].amount.0, 100);
+        assert_eq!(contract.get_distribution_round(DonationType::Direct, 0).unwrap().donor_count, 1);
+        assert_eq!(contract.get_distribution(DonationType::Direct).unwrap().round, 1);
+    }
+
+    #[test]
+    fn test_min_total_donation_counts_token_donations() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        contract.add_accepted_token(accounts(3));
+        contract.set_campaign_eligibility(
+            "campaign1".to_string(),
+            EligibilityRules {
+                min_total_donation: Some(U128(500)),
+                min_token_donations: vec![TokenAmount { token_id: accounts(3), amount: U128(700) }],
+                ..Default::default()
+            },
+        );
+
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        let msg = json!({ "donation_type": { "Campaign": { "campaign_id": "campaign1" } } }).to_string();
+        contract.ft_on_transfer(accounts(1), U128(500), msg.clone());
+        contract.ft_on_transfer(accounts(1), U128(250), msg.clone());
+        contract.ft_on_transfer(accounts(2), U128(500), msg);
+
+        assert!(contract.is_eligible(accounts(1), "campaign1".to_string()).eligible);
+        assert_eq!(
+            contract.is_eligible(accounts(2), "campaign1".to_string()).failed,
+            vec!["min_total_donation"]
+        );
+    }
+
+    #[test]
+    fn test_compute_airdrops_respects_max_rewards_per_donor() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+        contract.set_refund_window(5);
+        let campaign = DonationType::Campaign { campaign_id: "campaign1".to_string() };
+        contract.set_reward_policy(campaign.clone(), RewardPolicy::Ratio { ratio_bps: 1_000 });
+        contract.set_campaign_eligibility(
+            "campaign1".to_string(),
+            EligibilityRules { max_rewards_per_donor: Some(1), ..Default::default() },
+        );
+
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(1000)).build());
+        contract.record_donation(campaign.clone(), None, None, None);
+        contract.record_donation(campaign.clone(), None, None, None);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).block_timestamp(10).build());
+        let result = contract.compute_airdrops(campaign, 10);
+        assert_eq!(result.airdrops_created, 1);
+        assert_eq!(result.skipped, 1);
+        assert_eq!(contract.get_campaign_rewards("campaign1".to_string(), accounts(1)).record_count, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "The donation window cannot change once the campaign has donations")]
+    fn test_donation_window_is_fixed_once_donations_arrive() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        create_test_campaign(&mut contract, "campaign1");
+
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(NearToken::from_yoctonear(1000)).build());
+        contract.record_donation(DonationType::Campaign { campaign_id: "campaign1".to_string() }, None, None, None);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.set_campaign_eligibility(
+            "campaign1".to_string(),
+            EligibilityRules { donated_after: Some(100), ..Default::default() },
+        );
+    }
+
+    #[test]
+    fn test_refunded_donation_does_not_extend_streak() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut contract = DonorPayouts::new(None, None);
+        contract.set_refund_window(50);
+        contract.set_streak_config(StreakConfig { period_ns: 100, multipliers: vec![] });
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(10)
+            .attached_deposit(NearToken::from_yoctonear(1000))
+            .build());
+        let refunded_id = contract.record_donation(DonationType::Direct, None, None, None);
+        testing_env!(context.block_timestamp(20).attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.refund_donation(refunded_id);
+        assert_eq!(contract.get_donor_streak(accounts(1)).current_streak, 0);
+
+        // The next period's donation starts a fresh streak instead of extending the refunded one.
+        testing_env!(context.block_timestamp(150).attached_deposit(NearToken::from_yoctonear(1000)).build());
+        let donation_id = contract.record_donation(DonationType::Direct, None, None, None);
+        testing_env!(context.block_timestamp(210).attached_deposit(NearToken::from_yoctonear(0)).build());
+        contract.release_donation(donation_id);
+        callback_context(PromiseResult::Successful(vec![]));
+        contract.on_donation_forwarded(donation_id, accounts(0), vec![]);
+
+        assert_eq!(contract.get_donation(donation_id).unwrap().streak, 1);
+        let donor = contract.get_donor(accounts(1)).unwrap();
+        assert_eq!((donor.streak, donor.longest_streak), (1, 1));
+        assert_eq!(contract.get_donation(refunded_id).unwrap().streak, 0);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,83 @@
+use near_workspaces::types::Gas;
+use serde_json::json;
+
+/// Runs `verify_human` against a deployed registry and checks the cached answer gates airdrops.
+#[tokio::test]
+async fn test_verify_human_against_registry() -> Result<(), Box<dyn std::error::Error>> {
+    let sandbox = near_workspaces::sandbox().await?;
+    let contract = sandbox
+        .dev_deploy(&near_workspaces::compile_project("./").await?)
+        .await?;
+    let registry = sandbox
+        .dev_deploy(&near_workspaces::compile_project("./tests/human_registry_mock").await?)
+        .await?;
+    let human = sandbox.dev_create_account().await?;
+    let bot = sandbox.dev_create_account().await?;
+
+    registry
+        .call("new")
+        .args_json(json!({ "humans": [human.id()] }))
+        .transact()
+        .await?
+        .into_result()?;
+    contract
+        .call("new")
+        .args_json(json!({}))
+        .transact()
+        .await?
+        .into_result()?;
+    contract
+        .call("set_human_registry")
+        .args_json(json!({
+            "registry": {
+                "contract_id": registry.id(),
+                "method_name": "is_human",
+                "cache_ttl_ns": 3_600_000_000_000u64,
+                "mode": "Reject",
+            }
+        }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    for (account, expected) in [(&human, true), (&bot, false)] {
+        let is_human: bool = account
+            .call(contract.id(), "verify_human")
+            .args_json(json!({ "account_id": account.id() }))
+            .gas(Gas::from_tgas(50))
+            .transact()
+            .await?
+            .json()?;
+        assert_eq!(is_human, expected);
+        let check: serde_json::Value = contract
+            .view("get_human_check")
+            .args_json(json!({ "account_id": account.id() }))
+            .await?
+            .json()?;
+        assert_eq!(check["is_human"], expected);
+    }
+
+    let log_airdrop = |recipient| {
+        contract
+            .call("log_airdrop")
+            .args_json(json!({
+                "recipient": recipient,
+                "channel_id": "",
+                "donation_type": "Direct",
+                "amount": "100",
+            }))
+            .transact()
+    };
+    log_airdrop(human.id().clone()).await?.into_result()?;
+    let rejected = log_airdrop(bot.id().clone()).await?;
+    assert!(rejected.is_failure());
+    assert!(format!("{:?}", rejected.into_result().unwrap_err()).contains("not a verified human"));
+
+    let donor: serde_json::Value = contract
+        .view("get_donor")
+        .args_json(json!({ "wallet_id": human.id() }))
+        .await?
+        .json()?;
+    assert_eq!(donor["airdrop_amount"], "100");
+    Ok(())
+}
@@ -0,0 +1,22 @@
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::{near_bindgen, AccountId, PanicOnDefault};
+
+/// Answers `is_human` from a fixed list of accounts, like a human registry would.
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct HumanRegistryMock {
+    humans: Vec<AccountId>,
+}
+
+#[near_bindgen]
+impl HumanRegistryMock {
+    #[init]
+    pub fn new(humans: Vec<AccountId>) -> Self {
+        Self { humans }
+    }
+
+    pub fn is_human(&self, account_id: AccountId) -> bool {
+        self.humans.contains(&account_id)
+    }
+}